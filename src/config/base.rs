@@ -8,7 +8,6 @@
 
 use self::vars::method_set;
 use crate::config::*;
-use crate::drop::tool::ShouldResult;
 use std::fs::read_to_string;
 use std::path::Path;
 use std::process::exit;
@@ -36,6 +35,7 @@ pub fn parse_line(line: String, config: &mut Config, file: &str, line_number: i3
     if let Some(head) = line_splitted.next() {
         match head {
             "+" => method_add(method_args!()),
+            "+dir" => method_add_dir(method_args!()),
             "-" => method_remove(method_args!()),
             "$" => method_set(method_args!()),
             "#" => (),
@@ -48,6 +48,10 @@ pub fn parse_line(line: String, config: &mut Config, file: &str, line_number: i3
             "@gl" => method_import_gl(method_args!()),
             #[cfg(not(feature = "no-glisp"))]
             "@pipe" => method_import_pipe(method_args!()),
+            #[cfg(not(feature = "no-glisp"))]
+            "@ws" => method_import_websocket(method_args!()),
+            #[cfg(not(feature = "no-glisp"))]
+            "@sse" => method_import_sse(method_args!()),
             ">" => method_log(method_args!()),
             _ => {
                 if line.trim() != "" {
@@ -59,6 +63,7 @@ pub fn parse_line(line: String, config: &mut Config, file: &str, line_number: i3
 }
 
 pub fn syntax_error(file: &str, line_number: i32, error: &str) {
+    reload::count_error();
     log!(
         Error,
         format!(
@@ -78,14 +83,34 @@ where
     Ok(std::io::BufRead::lines(std::io::BufReader::new(file)))
 }
 
-fn method_import(args: MethodArgs) -> &mut Config {
+fn method_import(args: MethodArgs) {
     if let Some(head2) = args.line_splitted.next() {
-        read_config(head2.to_owned(), args.config).result_shldfatal(-1, || {})
+        if read_config(head2.to_owned(), args.config).is_err() && !is_running() {
+            exit(-1);
+        }
+    } else if is_running() {
+        syntax_error(args.file, args.line_number, LOG[18]);
     } else {
         log!(Fatal, LOG[18]);
         exit(-1);
     }
 }
+/// 读取 config 目录中一个被引入的脚本
+/// 服务器启动时找不到它是致命的错误，而热重载时它只是一个普通的错误，参见 reload::reload_config
+#[cfg(not(feature = "no-glisp"))]
+fn read_imported(file: &str, line_number: i32, name: &str) -> Option<String> {
+    match read_to_string("config/".to_owned() + name) {
+        Ok(a) => Some(a),
+        Err(_) if is_running() => {
+            syntax_error(file, line_number, &format!("{}{}", LOG[22], name));
+            None
+        }
+        Err(_) => {
+            log!(Fatal, format!("{}{}", LOG[22], name));
+            exit(-1);
+        }
+    }
+}
 
 fn method_add(args: MethodArgs) {
     if let Some(head2) = args.line_splitted.next() {
@@ -98,31 +123,75 @@ fn method_add(args: MethodArgs) {
             return;
         }
         args.config.router_config.serve_files_info.insert(
-            "/".to_owned() + head2,
+            route_key(head2),
             ServeFileData::from("/".to_owned() + head2, args.config),
         );
     } else {
         syntax_error(args.file, args.line_number, LOG[18]);
     }
 }
+/// 之后的项是可选的，可以是 MIME 类型（例如 `text/html` ）、允许的方法（例如 `GET,POST` ）
+/// 或者 `precompressed` ，它表示该路由会发送相邻的预压缩文件
+/// 因为 MIME 类型总是包含 `/` ，而方法总是大写的，所以它们的顺序是任意的
 fn method_add_head3_ext(args: MethodArgs, head2: &str, head3: &str) {
-    args.config.router_config.serve_files_info.insert(
-        "/".to_owned() + {
-            if head3 == "/" {
-                ""
-            } else {
-                head3
-            }
-        },
-        ServeFileData::from_with_content_type(
-            "/".to_owned() + head2,
-            if let Some(head4) = args.line_splitted.next() {
-                head4.to_string()
-            } else {
-                "text/html; charset=utf-8".to_string()
-            },
-        ),
+    let mut data = ServeFileData::from_with_content_type(
+        "/".to_owned() + head2,
+        "text/html; charset=utf-8".to_string(),
     );
+    for head in args.line_splitted.by_ref() {
+        if head.contains('/') {
+            data.content_type = head.to_string();
+        } else if head == "precompressed" {
+            data.precompressed = true;
+        } else if let Some(methods) = ServeFileData::parse_methods(head) {
+            data.methods = methods;
+        } else {
+            syntax_error(args.file, args.line_number, &format!("{}{}", LOG[17], head));
+            return;
+        }
+    }
+    args.config
+        .router_config
+        .serve_files_info
+        .insert(route_key(head3), data);
+}
+/// `+dir <export 中的目录> <URL 前缀> [autoindex] [precompressed]`
+/// 同一个 URL 前缀只能挂载一个目录，后挂载的会替换先挂载的
+fn method_add_dir(args: MethodArgs) {
+    let (head2, head3) = match (args.line_splitted.next(), args.line_splitted.next()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            syntax_error(args.file, args.line_number, LOG[18]);
+            return;
+        }
+    };
+    if !Path::new(&("export/".to_owned() + head2)).is_dir() {
+        syntax_error(args.file, args.line_number, LOG[43]);
+        return;
+    }
+    let mut data = ServeDirData {
+        dir_path: crate::drop::url::path_segments(head2)
+            .unwrap_or_default()
+            .iter()
+            .map(|a| "/".to_owned() + a)
+            .collect(),
+        url_segments: crate::drop::url::path_segments(head3).unwrap_or_default(),
+        autoindex: false,
+        precompressed: false,
+    };
+    for head in args.line_splitted.by_ref() {
+        match head {
+            "autoindex" => data.autoindex = true,
+            "precompressed" => data.precompressed = true,
+            _ => {
+                syntax_error(args.file, args.line_number, &format!("{}{}", LOG[17], head));
+                return;
+            }
+        }
+    }
+    let dirs = &mut args.config.router_config.serve_dirs;
+    dirs.retain(|a| a.url_segments != data.url_segments);
+    dirs.push(data);
 }
 fn method_remove(args: MethodArgs) {
     if let Some(head2) = args.line_splitted.next() {
@@ -132,27 +201,15 @@ fn method_remove(args: MethodArgs) {
     }
 }
 fn method_remove_head2_ext(args: MethodArgs, head2: &str) {
-    if head2 == "/" {
-        if args
-            .config
-            .router_config
-            .serve_files_info
-            .remove("/")
-            .is_some()
-        {
-        } else {
-            syntax_error(args.file, args.line_number, LOG[19]);
-        }
-    } else if args
+    if args
         .config
         .router_config
         .serve_files_info
-        .remove(&("/".to_owned() + head2))
-        .is_some()
+        .remove(&route_key(head2))
+        .is_none()
     {
-    } else {
         syntax_error(args.file, args.line_number, LOG[19]);
-    };
+    }
 }
 
 fn method_compile(args: MethodArgs) {
@@ -223,14 +280,17 @@ fn method_inject(mut args: MethodArgs) {
 #[cfg(not(feature = "no-glisp"))]
 fn method_import_gl(args: MethodArgs) {
     if let Some(head2) = args.line_splitted.next() {
+        let script = match read_imported(args.file, args.line_number, head2) {
+            Some(a) => a,
+            None => return,
+        };
         let env = &mut crate::glisp::core::default_env();
-        if GLISP_ENABLE_STACK.load(Ordering::Relaxed) {
+        if reload::Staged::staged(&GLISP_ENABLE_STACK) {
             crate::glisp::core::enable_stack();
         }
 
         match crate::glisp::core::parse_eval(
-            read_to_string("config/".to_owned() + head2)
-                .result_shldfatal(-1, || log!(Fatal, format!("{}{}", LOG[22], head2))),
+            script,
             env,
             Some(std::cell::RefCell::new(args.config).into()),
         ) {
@@ -248,12 +308,44 @@ fn method_import_gl(args: MethodArgs) {
 #[cfg(not(feature = "no-glisp"))]
 fn method_import_pipe(args: MethodArgs) {
     if let Some(head2) = args.line_splitted.next() {
-        args.config.router_config.pipe.push(
-            read_to_string("config/".to_owned() + head2)
-                .result_shldfatal(-1, || log!(Fatal, format!("{}{}", LOG[22], head2))),
-        );
+        if let Some(script) = read_imported(args.file, args.line_number, head2) {
+            args.config.router_config.pipe.push(script);
+        }
     }
 }
+#[cfg(not(feature = "no-glisp"))]
+fn method_import_websocket(args: MethodArgs) {
+    if let (Some(url), Some(head3)) = (args.line_splitted.next(), args.line_splitted.next()) {
+        if let Some(script) = read_imported(args.file, args.line_number, head3) {
+            args.config
+                .router_config
+                .websocket
+                .insert(route_key(url), script);
+        }
+    } else {
+        syntax_error(args.file, args.line_number, LOG[16]);
+    }
+}
+#[cfg(not(feature = "no-glisp"))]
+fn method_import_sse(args: MethodArgs) {
+    let (url, source) = match (
+        args.line_splitted.next(),
+        args.line_splitted.next(),
+        args.line_splitted.next(),
+    ) {
+        (Some(url), Some("gl"), Some(head4)) => {
+            match read_imported(args.file, args.line_number, head4) {
+                Some(script) => (url, SseSource::Script(script)),
+                None => return,
+            }
+        }
+        (Some(url), Some("watch"), Some(head4)) => {
+            (url, SseSource::Watch("export/".to_owned() + head4))
+        }
+        _ => return syntax_error(args.file, args.line_number, LOG[16]),
+    };
+    args.config.router_config.sse.insert(route_key(url), source);
+}
 fn method_log(args: MethodArgs) {
     log!(
         Info,
@@ -268,12 +360,11 @@ fn method_inject_haserr(args: &mut MethodArgs) -> Result<(), ()> {
     } else {
         return Err(());
     };
-    let temp_pathname = &("/".to_owned() + pathname);
     let conf_serve_value = if let Some(a) = args
         .config
         .router_config
         .serve_files_info
-        .get_mut(if pathname == "/" { "/" } else { temp_pathname })
+        .get_mut(&route_key(pathname))
     {
         a
     } else {
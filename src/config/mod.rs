@@ -38,18 +38,20 @@
 //! Ghost Code 和 Ghost Lisp 应该能被良好的对接  
 //! 事实上，完全可以将 Ghost Code 当作 Ghost Lisp 代码的“管理器”，让我们一眼就能明白该服务器有什么功能
 mod base;
+pub mod reload;
 mod vars;
 
 use crate::config::base::*;
-use crate::drop::http::HttpResponse;
 use crate::drop::log::LogLevel::*;
 use crate::i18n::LOG;
 use crate::macros::*;
 use core::sync::atomic::Ordering;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
+use std::sync::RwLock;
 
 pub static USE_LOCALTIME: AtomicBool = AtomicBool::new(true);
 pub static ENABLE_DEBUG: AtomicBool = AtomicBool::new(true);
@@ -62,10 +64,32 @@ pub static BOX_NUM_PER_THREAD_MAG: AtomicU32 = AtomicU32::new(1000); // 参见
 pub static BOX_NUM_PER_THREAD_INIT_MAG: AtomicU32 = AtomicU32::new(1000); // 参见引用之处
 pub static XRPS_PREDICT_MAG: AtomicU32 = AtomicU32::new(1100); // 参见引用之处
 pub static BOX_MODE: AtomicBool = AtomicBool::new(false); // 是否使用 box-mode
+pub static EVENT_MODE: AtomicBool = AtomicBool::new(false); // 是否使用基于 epoll 的 event-mode ，它优先于 box-mode
 pub static ENABLE_RETURN_IF_PIPE_ERR: AtomicBool = AtomicBool::new(true); // 参见引用之处
 pub static ENABLE_CODE_BAD_REQUEST: AtomicBool = AtomicBool::new(false); // 参见引用之处
 pub static ENABLE_CODE_NOT_FOUND: AtomicBool = AtomicBool::new(false); // 参见引用之处
-pub static mut GLOBAL_ROUTER_CONFIG: Option<Arc<RouterConfig>> = None; //每一个请求都会收到一个对其的引用
+pub static ENABLE_KEEP_ALIVE: AtomicBool = AtomicBool::new(true); // 是否允许 HTTP 持久连接
+pub static KEEP_ALIVE_TIMEOUT: AtomicU32 = AtomicU32::new(5); // 持久连接的空闲超时，以秒为单位
+pub static KEEP_ALIVE_MAX: AtomicU32 = AtomicU32::new(100); // 一个持久连接最多处理的请求数
+pub static STREAM_THRESHOLD: AtomicU32 = AtomicU32::new(1048576); // 大于该字节数的文件会被流式的发送
+pub static COMPRESS_MIN_SIZE: AtomicU32 = AtomicU32::new(256); // 小于该字节数的主体不会被压缩
+pub static HEADER_TIMEOUT: AtomicU32 = AtomicU32::new(10); // 读取请求行和请求头的总时限，以秒为单位，0 表示不限制
+pub static BODY_TIMEOUT: AtomicU32 = AtomicU32::new(30); // 读取请求主体的总时限，以秒为单位，0 表示不限制
+pub static MAX_REQUEST_LINE: AtomicU32 = AtomicU32::new(8192); // 请求行的最大字节数
+pub static MAX_HEADERS: AtomicU32 = AtomicU32::new(100); // 请求头的最大数量
+pub static MAX_HEADER_SIZE: AtomicU32 = AtomicU32::new(16384); // 所有请求头加起来的最大字节数
+pub static MAX_BODY_SIZE: AtomicU32 = AtomicU32::new(10485760); // 请求主体的最大字节数，0 表示不限制
+pub static STRICT_PARSING: AtomicBool = AtomicBool::new(true); // 是否拒绝格式错误或有歧义的请求
+pub static WEBSOCKET_TIMEOUT: AtomicU32 = AtomicU32::new(300); // WebSocket 连接在多少秒内没有收到任何帧就会被关闭，0 表示不限制
+pub static SSE_INTERVAL: AtomicU32 = AtomicU32::new(1); // SSE 的脚本每隔多少秒执行一次，被监视的文件每隔多少秒检查一次
+pub static SSE_HEARTBEAT: AtomicU32 = AtomicU32::new(15); // SSE 连接空闲多少秒后发送一个心跳注释，0 表示不发送
+pub static FILE_CACHE_SIZE: AtomicU32 = AtomicU32::new(0); // 文件缓存的最大字节数，0 表示不缓存
+pub static FOLLOW_SYMLINKS: AtomicBool = AtomicBool::new(true); // 是否跟随被托管的文件路径中的符号链接，参见 router::is_contained
+pub static QUEUE_DEPTH: AtomicU32 = AtomicU32::new(64); // 等待空闲线程的连接最多有多少个
+pub static QUEUE_OVERFLOW_REJECT: AtomicBool = AtomicBool::new(true); // 等待的连接过多时，是以 503 拒绝新的连接，还是等待
+pub static SHUTDOWN_TIMEOUT: AtomicU32 = AtomicU32::new(10); // 关闭时最多等待正在处理的连接多少秒，参见 mode::shutdown
+pub static CONFIG_WATCH: AtomicU32 = AtomicU32::new(0); // 每隔多少秒检查一次 config 目录中的文件是否被修改，0 表示不检查，参见 reload::watch
+static GLOBAL_ROUTER_CONFIG: RwLock<Option<Arc<RouterConfig>>> = RwLock::new(None); // 每一个连接都会收到一个对其的引用，热重载时它会被整个替换
 pub static mut SSL_CERTIFICATE: Option<Arc<Vec<u8>>> = None; //CA证书，这是 nightly 版本的一部分，仍在开发
 pub static mut SSL_PRIVATE_KEY: Option<Arc<Vec<u8>>> = None; //公钥，这是nightly版本的一部分，以后会被移除
 
@@ -85,19 +109,42 @@ pub struct ReplaceData {
 /// 这是 Router 的配置文件，每个请求都有一份引用或拷贝  
 /// 如果可能，应该尽量作为引用而非拷贝  
 /// serve_file_info: 要挂载的文件，其中键是最终的 URL  
-/// response_404: 404 NOT FOUND 响应，如果没有开启 404 状态码则不需要设置  
-/// pipe: pipe 字符串（而非文件）的列表，会被从前往后的读取
+/// serve_dirs: 要挂载的目录，只有在 serve_file_info 中找不到请求的 URL 时才会被使用  
+/// mime_bind: 所有额外的 MIME 类型绑定的集合，键是文件后缀名，值的类型的标准名  
+/// roots: 被托管的文件只能位于这些目录之中，每一项是 (配置中的相对路径, 规范化的绝对路径) ，`export` 总是其中之一  
+/// error_pages: 错误页面，其中键是状态码，值是页面的模板（而非文件），参见 router::set_error_page  
+/// pipe: pipe 字符串（而非文件）的列表，会被从前往后的读取  
+/// compress_types: 需要被压缩的 MIME 类型，例如 `text/html` ，不包含 `; charset=utf-8` 之类的参数
+/// websocket: WebSocket 路由，其中键是 URL ，值是处理每一条消息的 Glisp 字符串（而非文件）
+/// sse: Server-Sent Events 路由，其中键是 URL ，参见 SseSource
 #[derive(Clone, Default)]
 pub struct RouterConfig {
     pub serve_files_info: HashMap<String, ServeFileData>,
-    pub response_404: Option<HttpResponse>,
+    pub serve_dirs: Vec<ServeDirData>,
+    pub mime_bind: HashMap<String, String>,
+    pub roots: Vec<(PathBuf, PathBuf)>,
+    pub error_pages: HashMap<u16, String>,
     pub pipe: Vec<String>,
+    pub compress_types: Vec<String>,
+    pub websocket: HashMap<String, String>,
+    pub sse: HashMap<String, SseSource>,
+}
+
+/// SSE 路由的事件来源
+/// Script: 每隔 sse-interval 秒执行一次的 Glisp 字符串（而非文件）
+/// Watch: 被监视的文件的路径，文件中新增的每一行都是一个事件
+#[derive(Clone)]
+pub enum SseSource {
+    Script(String),
+    Watch(String),
 }
 
 /// 该结构体用以存储一个被托管的文件对应的元数据  
 /// file_path: 被托管的文件的路径  
 /// content_type: 被托管的文件的 MIME 类型，例如 `application/octet-stream`  
-/// replace: 可选的，如果该文件里包含 `$_grflags` ，则存储它们及其对应的元数据
+/// replace: 可选的，如果该文件里包含 `$_grflags` ，则存储它们及其对应的元数据  
+/// methods: 该路由允许的请求方法，默认是 `GET` 和 `HEAD` ，`OPTIONS` 总是被允许的  
+/// precompressed: 是否在客户端接受时发送相邻的 `.br` 或 `.gz` 预压缩文件，默认关闭
 ///
 /// 关于 MIME 类型的标准名，参见[此文档](https://datatracker.ietf.org/doc/html/rfc6838)
 #[derive(Clone)]
@@ -105,14 +152,39 @@ pub struct ServeFileData {
     pub file_path: String,
     pub content_type: String,
     pub replace: Option<Vec<ReplaceData>>,
+    pub methods: Vec<String>,
+    pub precompressed: bool,
+}
+
+/// 该结构体用以存储一个被挂载的目录对应的元数据，目录中的每一个文件都以其相对路径作为 URL 被托管  
+/// dir_path: 被挂载的目录在 export 中的路径，例如 `/static` ，挂载整个 export 时是空字符串  
+/// url_segments: URL 前缀的路径段，例如 `/assets/` 对应 `["assets"]`  
+/// autoindex: 目录中没有 `index.html` 时，是否生成一个列出目录内容的页面，默认关闭  
+/// precompressed: 与 ServeFileData 的相同
+#[derive(Clone)]
+pub struct ServeDirData {
+    pub dir_path: String,
+    pub url_segments: Vec<String>,
+    pub autoindex: bool,
+    pub precompressed: bool,
+}
+
+/// 一个监听地址及其选项
+/// addr: 地址，例如 `127.0.0.1:80` 或 `[::]:80`  
+/// https: 这个地址上的连接是否使用 TLS ，这是 nightly 版本的一部分，仍在开发  
+/// backlog: 已经建立但还没有被接受的连接最多有多少个
+#[derive(Clone)]
+pub struct AddrBind {
+    pub addr: String,
+    pub https: bool,
+    pub backlog: u32,
 }
 
 /// 这是总的配置文件，应该尽量避免拷贝，应该尽量保证唯一性，因为拷贝的代价很大  
 /// use_localtime: 是否使用本地时间而非 UTC 时间  
 /// enable_debug: 是否使用 debug 模式运行本程序，这主要跟日志的输出有关，debug 模式会极大的拖慢性能  
-/// addr_bind: 所有 IP 绑定的集合，例如 ["127.0.0.1:80", "127.0.0.1:22397", "\[fe80::0\]:80"]  
-/// mime_bind: 所有额外的 MIME 类型绑定的集合，键是文件后缀名，值的类型的标准名  
-/// status_codes: 启用的所有状态码，例如 [400, 404]
+/// addr_bind: 所有监听地址的集合，每一个地址都有一个独立的监听器，参见 AddrBind  
+/// status_codes: 启用的所有状态码，例如 [400, 404] ，其它的状态码总是启用的，所以只有 400 和 404 会影响服务器的行为
 ///
 /// 关于 MIME 类型的标准名，参见[此文档](https://datatracker.ietf.org/doc/html/rfc6838)  
 /// 关于所有的状态码，参见[此文档](https://datatracker.ietf.org/doc/html/rfc7231)  
@@ -121,20 +193,28 @@ pub struct ServeFileData {
 pub struct Config {
     pub use_localtime: bool,
     pub enable_debug: bool,
-    pub addr_bind: Vec<String>,
+    pub addr_bind: Vec<AddrBind>,
     pub router_config: RouterConfig,
-    pub mime_bind: HashMap<String, String>,
     pub status_codes: Vec<u16>,
 }
 
+/// 将配置中的 URL 转换为 serve_files_info 的键
+/// 它和请求的路径使用同样的方式被解码和规范化，参见 drop::url::parse_target
+/// 例如 `my%20file.png` 会被转换为 `/my file.png`
+pub fn route_key(url: &str) -> String {
+    match crate::drop::url::parse_target(&("/".to_owned() + url)) {
+        Some((path, _, _)) => path,
+        None => "/".to_owned() + url,
+    }
+}
+
 impl ServeFileData {
     pub fn from(file_path: String, config: &Config) -> Self {
         ServeFileData {
-            content_type: match file_path.rsplit('.').next() {
-                Some(a) => Self::auto_content_type(a.to_owned(), config),
-                _ => "application/octet-stream".to_owned(),
-            },
+            content_type: Self::auto_content_type(&file_path, &config.router_config.mime_bind),
             replace: None,
+            methods: Self::default_methods(),
+            precompressed: false,
             file_path,
         }
     }
@@ -142,14 +222,46 @@ impl ServeFileData {
         ServeFileData {
             content_type,
             replace: None,
+            methods: Self::default_methods(),
+            precompressed: false,
             file_path,
         }
     }
-    fn auto_content_type(ex_name: String, config: &Config) -> String {
-        if let Some(mime_type) = config.mime_bind.get(&ex_name) {
+    pub fn default_methods() -> Vec<String> {
+        vec!["GET".to_owned(), "HEAD".to_owned()]
+    }
+    /// 解析形如 `GET,HEAD,POST` 的方法列表
+    /// 允许 `GET` 就意味着允许 `HEAD` ，参见 RFC 9110 9.1
+    pub fn parse_methods(str: &str) -> Option<Vec<String>> {
+        let mut methods: Vec<String> = vec![];
+        for method in str.split(',') {
+            if method.is_empty() || !method.bytes().all(|a| a.is_ascii_uppercase()) {
+                return None;
+            }
+            if !methods.iter().any(|a| a == method) {
+                methods.push(method.to_owned());
+            }
+        }
+        if methods.iter().any(|a| a == "GET") && !methods.iter().any(|a| a == "HEAD") {
+            methods.push("HEAD".to_owned());
+        }
+        Some(methods)
+    }
+    /// `Allow` 响应头的值，例如 `GET, HEAD, OPTIONS`
+    pub fn allow_header(&self) -> String {
+        let mut methods = self.methods.clone();
+        if !methods.iter().any(|a| a == "OPTIONS") {
+            methods.push("OPTIONS".to_owned());
+        }
+        methods.join(", ")
+    }
+    /// 根据文件的后缀名推断 MIME 类型，`+mime` 绑定的类型优先
+    pub fn auto_content_type(file_path: &str, mime_bind: &HashMap<String, String>) -> String {
+        let ex_name = file_path.rsplit('.').next().unwrap_or_default();
+        if let Some(mime_type) = mime_bind.get(ex_name) {
             mime_type.to_string()
         } else {
-            match ex_name.as_str() {
+            match ex_name {
                 "html" => "text/html",
                 "css" => "text/css",
                 "js" => "text/javascript",
@@ -168,18 +280,25 @@ impl ServeFileData {
 
 impl Config {
     pub fn new() -> Self {
-        Config {
+        let mut config = Config {
             use_localtime: true,
             enable_debug: false,
             addr_bind: vec![],
             router_config: RouterConfig {
                 serve_files_info: HashMap::new(),
-                response_404: None,
+                serve_dirs: vec![],
+                mime_bind: HashMap::new(),
+                roots: vec![],
+                error_pages: HashMap::new(),
                 pipe: vec![],
+                compress_types: vec![],
+                websocket: HashMap::new(),
+                sse: HashMap::new(),
             },
-            mime_bind: HashMap::new(),
             status_codes: vec![],
-        }
+        };
+        config.add_root("export");
+        config
     }
     /// 对于部分选项，难以在它们的引用处给它们完整的 config
     /// 所以，一些选项不得已的要被推送到全局
@@ -187,24 +306,51 @@ impl Config {
     pub fn sync_static_vars(&self) {
         USE_LOCALTIME.store(self.use_localtime, Ordering::Relaxed);
         ENABLE_DEBUG.store(self.enable_debug, Ordering::Relaxed);
-        unsafe { GLOBAL_ROUTER_CONFIG = Some(Arc::new(self.clone().router_config)) };
-        if !self.router_config.pipe.is_empty() {
-            ENABLE_PIPE.store(true, Ordering::Relaxed)
-        }
-        if self.status_codes.get(400).is_some() {
-            ENABLE_CODE_BAD_REQUEST.store(true, Ordering::Relaxed)
-        }
-        if self.status_codes.get(404).is_some() {
-            ENABLE_CODE_NOT_FOUND.store(true, Ordering::Relaxed)
+        *GLOBAL_ROUTER_CONFIG
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(self.clone().router_config));
+        ENABLE_PIPE.store(!self.router_config.pipe.is_empty(), Ordering::Relaxed);
+        ENABLE_CODE_BAD_REQUEST.store(self.status_codes.contains(&400), Ordering::Relaxed);
+        ENABLE_CODE_NOT_FOUND.store(self.status_codes.contains(&404), Ordering::Relaxed);
+    }
+    /// 添加一个被托管的文件可以位于的目录，返回值说明了它是否是一个存在的目录
+    pub fn add_root(&mut self, path: &str) -> bool {
+        let real_path = match std::fs::canonicalize(path) {
+            Ok(a) if a.is_dir() => a,
+            _ => return false,
+        };
+        let path = crate::router::normalize_path(std::path::Path::new(path));
+        let roots = &mut self.router_config.roots;
+        if !roots.iter().any(|(a, _)| *a == path) {
+            roots.push((path, real_path));
         }
+        true
     }
     /// 检查 Config 是否已经准备就绪
     pub fn check(&self) {
-        if self.router_config.serve_files_info.is_empty() {
+        if self.router_config.serve_files_info.is_empty()
+            && self.router_config.serve_dirs.is_empty()
+        {
             log!(Warn, LOG[13]);
         }
     }
 }
+/// 返回当前的路由配置
+/// 每一个连接在开始时获取一次，所以热重载不会影响正在处理的请求，参见 reload::reload_config
+pub fn router_config() -> Arc<RouterConfig> {
+    GLOBAL_ROUTER_CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap()
+}
+/// 服务器是否已经开始运行，此时配置中的错误不应该让服务器退出
+pub fn is_running() -> bool {
+    GLOBAL_ROUTER_CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+}
 /// 读取一个配置文件簇的主配置文件
 /// 例如，`main.gc`，我们将尽可能多个有关联（例如相互“引入”）的配置文件成为一个配置文件簇
 /// 在主配置文件簇中，`main.gc`是主配置文件，因为它不被任何其它的配置文件引入
@@ -212,6 +358,7 @@ pub fn read_config(filename: String, config: &mut Config) -> Result<&mut Config,
     let lines = if let Ok(lines) = read_lines("config/".to_owned() + &filename) {
         lines
     } else {
+        reload::count_error();
         log!(
             Error,
             format!("{}{}", LOG[9], "config/".to_owned() + &filename)
@@ -228,17 +375,20 @@ pub fn read_config(filename: String, config: &mut Config) -> Result<&mut Config,
                 &("config/".to_owned() + &filename),
                 line_number,
             ),
-            Err(_) => log!(
-                Error,
-                format!(
-                    "{}{}{} {}{}",
-                    LOG[10],
-                    LOG[11],
-                    "config/".to_owned() + &filename,
-                    LOG[12],
-                    line_number
+            Err(_) => {
+                reload::count_error();
+                log!(
+                    Error,
+                    format!(
+                        "{}{}{} {}{}",
+                        LOG[10],
+                        LOG[11],
+                        "config/".to_owned() + &filename,
+                        LOG[12],
+                        line_number
+                    )
                 )
-            ),
+            }
         }
         line_number += 1;
     }
@@ -0,0 +1,205 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 配置的热重载
+//! 收到 SIGHUP 或者 config 目录中的文件被修改时， `main.gc` 会被重新读取
+//! 只有没有任何错误的配置才会生效，否则旧的配置会被完整的保留
+//! 新的路由配置只对新的连接生效，已经建立的连接会继续使用它们开始时的配置
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use super::*;
+use crate::drop::signal::{self, SIGHUP};
+
+/// 读取配置时发生的错误的数量，参见 base::syntax_error
+static CONFIG_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+type Vars = (
+    &'static [&'static AtomicU32],
+    &'static [&'static AtomicBool],
+);
+
+/// 由 `$` 选项直接设置的全局选项，重载时它们会先被恢复为默认值，这样，从配置中删去的选项也会失效
+/// 由 Config 推送到全局的选项不在此列，参见 Config::sync_static_vars
+/// 不在此列的 `$` 选项（例如 threads 、 queue-depth 、 box-mode ）只在启动时生效，重载时它们会被忽略
+static RELOADABLE_VARS: Vars = (
+    &[
+        &KEEP_ALIVE_TIMEOUT,
+        &KEEP_ALIVE_MAX,
+        &STREAM_THRESHOLD,
+        &COMPRESS_MIN_SIZE,
+        &HEADER_TIMEOUT,
+        &BODY_TIMEOUT,
+        &MAX_REQUEST_LINE,
+        &MAX_HEADERS,
+        &MAX_HEADER_SIZE,
+        &MAX_BODY_SIZE,
+        &WEBSOCKET_TIMEOUT,
+        &SSE_INTERVAL,
+        &SSE_HEARTBEAT,
+        &FILE_CACHE_SIZE,
+        &SHUTDOWN_TIMEOUT,
+        &CONFIG_WATCH,
+    ],
+    &[
+        &ENABLE_RETURN_IF_PIPE_ERR,
+        &ENABLE_KEEP_ALIVE,
+        &STRICT_PARSING,
+        &FOLLOW_SYMLINKS,
+        &GLISP_DEBUG,
+        &GLISP_ENABLE_STACK,
+        &QUEUE_OVERFLOW_REJECT,
+    ],
+);
+static DEFAULT_VARS: OnceLock<Snapshot> = OnceLock::new();
+/// 重载时，新读取的选项被暂存在这里，而不是直接写入全局选项，参见 Staged
+static STAGED_VARS: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+/// 一组全局选项在某一时刻的值
+#[derive(Clone)]
+struct Snapshot(Vec<u32>, Vec<bool>);
+impl Snapshot {
+    fn save(vars: Vars) -> Self {
+        Snapshot(
+            vars.0.iter().map(|a| a.load(Ordering::Relaxed)).collect(),
+            vars.1.iter().map(|a| a.load(Ordering::Relaxed)).collect(),
+        )
+    }
+    fn restore(&self, vars: Vars) {
+        for (var, value) in vars.0.iter().zip(&self.0) {
+            var.store(*value, Ordering::Relaxed);
+        }
+        for (var, value) in vars.1.iter().zip(&self.1) {
+            var.store(*value, Ordering::Relaxed);
+        }
+    }
+}
+
+fn lock_staged() -> std::sync::MutexGuard<'static, Option<Snapshot>> {
+    STAGED_VARS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 读取配置时，`$` 选项都通过这个 trait 被设置和读取
+/// 启动时，它们直接作用于全局选项；重载时，它们只作用于暂存的值，正在处理的请求不会看到一个读取到一半的配置
+pub trait Staged<T> {
+    fn stage(&self, value: T);
+    fn staged(&self) -> T;
+}
+macro_rules! impl_staged {
+    ($type:ty, $value:ty, $field:tt) => {
+        impl Staged<$value> for $type {
+            fn stage(&self, value: $value) {
+                let mut staged = lock_staged();
+                match staged.as_mut() {
+                    None => self.store(value, Ordering::Relaxed),
+                    Some(a) => {
+                        let vars = RELOADABLE_VARS.$field;
+                        if let Some(i) = vars.iter().position(|a| std::ptr::eq(*a, self)) {
+                            a.$field[i] = value;
+                        }
+                    }
+                }
+            }
+            fn staged(&self) -> $value {
+                if let Some(a) = lock_staged().as_ref() {
+                    let vars = RELOADABLE_VARS.$field;
+                    if let Some(i) = vars.iter().position(|a| std::ptr::eq(*a, self)) {
+                        return a.$field[i];
+                    }
+                }
+                self.load(Ordering::Relaxed)
+            }
+        }
+    };
+}
+impl_staged!(AtomicU32, u32, 0);
+impl_staged!(AtomicBool, bool, 1);
+
+pub fn count_error() {
+    CONFIG_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 记录全局选项的默认值，必须在第一次读取配置之前被调用
+pub fn save_defaults() {
+    DEFAULT_VARS.get_or_init(|| Snapshot::save(RELOADABLE_VARS));
+}
+
+/// 重新读取 `main.gc` ，如果其中没有任何错误，则替换当前的配置，否则保留旧的配置
+/// 新的选项在读取时只被暂存，通过检查之后才会与新的路由配置一起被提交
+/// 监听的地址和 threads 、 box-mode 等选项只在启动时生效，修改它们需要重新启动服务器
+pub fn reload_config() -> bool {
+    let defaults = DEFAULT_VARS
+        .get()
+        .cloned()
+        .unwrap_or_else(|| Snapshot::save(RELOADABLE_VARS));
+    *lock_staged() = Some(defaults);
+
+    let errors = CONFIG_ERRORS.load(Ordering::Relaxed);
+    let mut config = Config::new();
+    let is_ok = read_config("main.gc".to_owned(), &mut config).is_ok();
+    let errors = CONFIG_ERRORS.load(Ordering::Relaxed) - errors;
+    let staged = lock_staged().take();
+    if !is_ok || errors != 0 {
+        log!(Error, format!("{}{}", LOG[46], errors));
+        return false;
+    }
+
+    config.check();
+    if let Some(a) = staged {
+        a.restore(RELOADABLE_VARS);
+    }
+    config.sync_static_vars();
+    log!(Info, LOG[45]);
+    true
+}
+
+/// 开始监听 SIGHUP ，并在另一个线程中等待重载的请求
+/// 如果设置了 config-watch ，这个线程还会定期检查 config 目录中的文件是否被修改
+pub fn watch() {
+    signal::listen(SIGHUP);
+    std::thread::spawn(|| {
+        let mut last_modified = config_modified();
+        let mut elapsed = 0;
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            let mut should_reload = signal::take(SIGHUP);
+            let interval = CONFIG_WATCH.load(Ordering::Relaxed);
+            if interval != 0 && elapsed >= interval {
+                elapsed = 0;
+                should_reload |= config_modified() != last_modified;
+            }
+            if should_reload {
+                last_modified = config_modified();
+                reload_config();
+            }
+        }
+    });
+}
+
+/// config 目录（包括其子目录）中最后一次被修改的文件的修改时间
+fn config_modified() -> Option<SystemTime> {
+    fn walk(path: &std::path::Path) -> Option<SystemTime> {
+        let mut modified = std::fs::metadata(path).ok()?.modified().ok();
+        for entry in std::fs::read_dir(path).ok()?.flatten() {
+            let a = match entry.file_type() {
+                Ok(a) if a.is_dir() => walk(&entry.path()),
+                _ => entry.metadata().ok().and_then(|a| a.modified().ok()),
+            };
+            modified = modified.max(a);
+        }
+        modified
+    }
+    walk(std::path::Path::new("config"))
+}
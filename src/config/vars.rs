@@ -6,26 +6,36 @@
  * if not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{drop::log::LogLevel::*, macros::*};
-
+use super::reload::Staged;
 use super::*;
 
 pub fn method_set(args: MethodArgs) {
     macro_rules! float_read_to {
         ($var:ident, $field:ident) => {
-            $var.store(
-                if let Ok(a) = $field.parse::<f32>() {
-                    (a * 1000.0) as u32
-                } else {
-                    syntax_error(
-                        args.file,
-                        args.line_number,
-                        &format!("{}{}", LOG[17], $field),
-                    );
-                    $var.load(Ordering::Relaxed)
-                },
-                Ordering::Relaxed,
-            )
+            $var.stage(if let Ok(a) = $field.parse::<f32>() {
+                (a * 1000.0) as u32
+            } else {
+                syntax_error(
+                    args.file,
+                    args.line_number,
+                    &format!("{}{}", LOG[17], $field),
+                );
+                $var.staged()
+            })
+        };
+    }
+    macro_rules! u32_read_to {
+        ($var:ident, $field:ident) => {
+            $var.stage(if let Ok(a) = $field.parse() {
+                a
+            } else {
+                syntax_error(
+                    args.file,
+                    args.line_number,
+                    &format!("{}{}", LOG[17], $field),
+                );
+                $var.staged()
+            })
         };
     }
     if let Some(head2) = args.line_splitted.next() {
@@ -45,8 +55,8 @@ pub fn method_set(args: MethodArgs) {
                 ),
                 "+errpage" => {
                     if let Some(head4) = args.line_splitted.next() {
-                        if head3 == "404" {
-                            page_404_option(args, head4);
+                        if let Some(code) = status_code_option(head3).filter(|a| *a >= 400) {
+                            error_page_option(args, code, head4);
                         } else {
                             syntax_error(
                                 args.file,
@@ -58,38 +68,45 @@ pub fn method_set(args: MethodArgs) {
                         syntax_error(args.file, args.line_number, LOG[18]);
                     }
                 }
-                "+addr" => args.config.addr_bind.push(head3.to_owned()),
+                "+addr" => addr_option(args, head3),
+                "+root" => {
+                    if !args.config.add_root(head3) {
+                        syntax_error(args.file, args.line_number, LOG[43]);
+                    }
+                }
+                "follow-symlinks" => {
+                    let mut value = true;
+                    pas_bool_option(&mut value, head3, args.file, args.line_number);
+                    FOLLOW_SYMLINKS.stage(value);
+                }
                 "+mime" => {
                     if let Some(head4) = args.line_splitted.next() {
                         args.config
+                            .router_config
                             .mime_bind
                             .insert(head3.to_owned(), head4.to_owned());
                     } else {
                         syntax_error(args.file, args.line_number, LOG[18]);
                     }
                 }
-                "+code" => match head3 {
-                    "400" => args.config.status_codes.push(400),
-                    "404" => args.config.status_codes.push(404),
-                    _ => syntax_error(
+                "+compress" => args
+                    .config
+                    .router_config
+                    .compress_types
+                    .push(head3.to_ascii_lowercase()),
+                "compress-min-size" => u32_read_to!(COMPRESS_MIN_SIZE, head3),
+                "+code" => match status_code_option(head3) {
+                    Some(code) if !args.config.status_codes.contains(&code) => {
+                        args.config.status_codes.push(code)
+                    }
+                    Some(_) => (),
+                    None => syntax_error(
                         args.file,
                         args.line_number,
                         &format!("{}{}", LOG[36], head3),
                     ),
                 },
-                "threads" => THREADS_NUM.store(
-                    if let Ok(a) = head3.parse() {
-                        a
-                    } else {
-                        syntax_error(
-                            args.file,
-                            args.line_number,
-                            &format!("{}{}", LOG[17], head3),
-                        );
-                        THREADS_NUM.load(Ordering::Relaxed)
-                    },
-                    Ordering::Relaxed,
-                ),
+                "threads" => u32_read_to!(THREADS_NUM, head3),
                 "ssl-certificate" => {
                     #[cfg(feature = "nightly")]
                     unsafe {
@@ -106,6 +123,47 @@ pub fn method_set(args: MethodArgs) {
                         )); // TODO: Error log
                     }
                 }
+                "keep-alive" => {
+                    let mut value = true;
+                    pas_bool_option(&mut value, head3, args.file, args.line_number);
+                    ENABLE_KEEP_ALIVE.stage(value);
+                }
+                "keep-alive-timeout" => u32_read_to!(KEEP_ALIVE_TIMEOUT, head3),
+                "keep-alive-max" => u32_read_to!(KEEP_ALIVE_MAX, head3),
+                "stream-threshold" => u32_read_to!(STREAM_THRESHOLD, head3),
+                "file-cache-size" => u32_read_to!(FILE_CACHE_SIZE, head3),
+                "header-timeout" => u32_read_to!(HEADER_TIMEOUT, head3),
+                "body-timeout" => u32_read_to!(BODY_TIMEOUT, head3),
+                "max-request-line" => u32_read_to!(MAX_REQUEST_LINE, head3),
+                "max-headers" => u32_read_to!(MAX_HEADERS, head3),
+                "max-header-size" => u32_read_to!(MAX_HEADER_SIZE, head3),
+                "max-body-size" => u32_read_to!(MAX_BODY_SIZE, head3),
+                "websocket-timeout" => u32_read_to!(WEBSOCKET_TIMEOUT, head3),
+                "sse-interval" => {
+                    if head3.parse() == Ok(0) {
+                        syntax_error(args.file, args.line_number, LOG[56]);
+                    } else {
+                        u32_read_to!(SSE_INTERVAL, head3)
+                    }
+                }
+                "sse-heartbeat" => u32_read_to!(SSE_HEARTBEAT, head3),
+                "queue-depth" => u32_read_to!(QUEUE_DEPTH, head3),
+                "queue-overflow" => match head3 {
+                    "503" => QUEUE_OVERFLOW_REJECT.stage(true),
+                    "wait" => QUEUE_OVERFLOW_REJECT.stage(false),
+                    _ => syntax_error(
+                        args.file,
+                        args.line_number,
+                        &format!("{}{}", LOG[17], head3),
+                    ),
+                },
+                "shutdown-timeout" => u32_read_to!(SHUTDOWN_TIMEOUT, head3),
+                "config-watch" => u32_read_to!(CONFIG_WATCH, head3),
+                "strict-parsing" => {
+                    let mut value = true;
+                    pas_bool_option(&mut value, head3, args.file, args.line_number);
+                    STRICT_PARSING.stage(value);
+                }
                 "xrps-counter-cache-size" => float_read_to!(XRPS_COUNTER_CACHE_SIZE, head3),
                 "box-num-per-thread-mag" => float_read_to!(BOX_NUM_PER_THREAD_MAG, head3),
                 "box-num-per-thread-init-mag" => float_read_to!(BOX_NUM_PER_THREAD_INIT_MAG, head3),
@@ -113,22 +171,27 @@ pub fn method_set(args: MethodArgs) {
                 "box-mode" => {
                     let mut value = false;
                     pas_bool_option(&mut value, head3, args.file, args.line_number);
-                    BOX_MODE.store(value, Ordering::Relaxed);
+                    BOX_MODE.stage(value);
+                }
+                "event-mode" => {
+                    let mut value = false;
+                    pas_bool_option(&mut value, head3, args.file, args.line_number);
+                    EVENT_MODE.stage(value);
                 }
                 "return-if-pipe-err" => {
                     let mut value = false;
                     pas_bool_option(&mut value, head3, args.file, args.line_number);
-                    ENABLE_RETURN_IF_PIPE_ERR.store(value, Ordering::Relaxed);
+                    ENABLE_RETURN_IF_PIPE_ERR.stage(value);
                 }
                 "gl-debug" => {
                     let mut value = false;
                     pas_bool_option(&mut value, head3, args.file, args.line_number);
-                    GLISP_DEBUG.store(value, Ordering::Relaxed);
+                    GLISP_DEBUG.stage(value);
                 }
                 "gl-stack" => {
                     let mut value = true;
                     pas_bool_option(&mut value, head3, args.file, args.line_number);
-                    GLISP_ENABLE_STACK.store(value, Ordering::Relaxed);
+                    GLISP_ENABLE_STACK.stage(value);
                 }
                 _ => syntax_error(
                     args.file,
@@ -144,6 +207,33 @@ pub fn method_set(args: MethodArgs) {
     }
 }
 
+/// `$ +addr <地址> [http|https] [backlog]` ，两个可选项的顺序是任意的
+fn addr_option(args: MethodArgs, head3: &str) {
+    let mut bind = AddrBind {
+        addr: head3.to_owned(),
+        https: false,
+        backlog: crate::drop::socket::DEFAULT_BACKLOG,
+    };
+    for head in args.line_splitted.by_ref() {
+        match head {
+            "http" => bind.https = false,
+            "https" if cfg!(feature = "nightly") => bind.https = true,
+            "https" => return syntax_error(args.file, args.line_number, LOG[55]),
+            _ => match head.parse() {
+                Ok(a) => bind.backlog = a,
+                Err(_) => {
+                    return syntax_error(
+                        args.file,
+                        args.line_number,
+                        &format!("{}{}", LOG[17], head),
+                    )
+                }
+            },
+        }
+    }
+    args.config.addr_bind.push(bind);
+}
+
 fn pas_bool_option(option: &mut bool, opt_str: &str, file: &str, line_number: i32) {
     if opt_str == "yes" {
         *option = true;
@@ -154,15 +244,23 @@ fn pas_bool_option(option: &mut bool, opt_str: &str, file: &str, line_number: i3
     }
 }
 
-fn page_404_option(args: MethodArgs, head3: &str) {
-    let mut res = HttpResponse::new();
-    res.set_content(
-        if let Ok(a) = std::fs::read_to_string("export/".to_owned() + head3) {
-            a.into()
-        } else {
-            log!(Error, format!("{}{}", LOG[22], head3));
-            return;
-        },
-    );
-    args.config.router_config.response_404 = Some(res);
+/// 只接受 drop::http::status 中已注册的状态码
+fn status_code_option(str: &str) -> Option<u16> {
+    str.parse()
+        .ok()
+        .filter(|a| crate::drop::http::status(*a).is_some())
+}
+
+fn error_page_option(args: MethodArgs, code: u16, head4: &str) {
+    let page = if let Ok(a) = std::fs::read_to_string("export/".to_owned() + head4) {
+        a
+    } else {
+        syntax_error(
+            args.file,
+            args.line_number,
+            &format!("{}{}", LOG[22], "export/".to_owned() + head4),
+        );
+        return;
+    };
+    args.config.router_config.error_pages.insert(code, page);
 }
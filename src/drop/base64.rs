@@ -5,6 +5,27 @@
  * along with this program;
  * if not, see <https://www.gnu.org/licenses/>.
  */
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// 以标准的 Base64 字母表编码，并用 `=` 补齐，参见 RFC 4648 4
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |a, (i, b)| a | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(buffer >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 #[cfg(feature = "nightly")]
 pub fn decode_unchecked(base64str: &str) -> Vec<u8> {
     let mut decoded = Vec::new(); // 存储解码后的字节
@@ -41,6 +62,13 @@ pub fn decode_unchecked(base64str: &str) -> Vec<u8> {
 mod tests {
     use super::*;
     #[test]
+    fn encoding() {
+        assert_eq!(encode(b"Base64DECODEtest"), "QmFzZTY0REVDT0RFdGVzdA==");
+        assert_eq!(encode(b"ab"), "YWI=");
+        assert_eq!(encode(b""), "");
+    }
+    #[cfg(feature = "nightly")]
+    #[test]
     fn decode() {
         let base64 = "QmFzZTY0REVDT0RFdGVzdA==";
         assert_eq!(decode_unchecked(base64), b"Base64DECODEtest");
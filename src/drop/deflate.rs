@@ -0,0 +1,588 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! DEFLATE 压缩算法，以及基于它的 zlib 和 gzip 格式
+//! 参见 [RFC 1951](https://www.rfc-editor.org/rfc/rfc1951) 、
+//! [RFC 1950](https://www.rfc-editor.org/rfc/rfc1950) 和 [RFC 1952](https://www.rfc-editor.org/rfc/rfc1952)
+//!
+//! 只实现了压缩，使用哈希链查找 LZ77 匹配，并为每一个块构造动态哈夫曼编码
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// 查找匹配时最多沿哈希链回溯的次数，越大压缩率越高，但也越慢
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: u32 = 15;
+/// 每一个块最多包含的符号数
+const BLOCK_SYMBOLS: usize = 1 << 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// 码长编码的码长被写出的顺序
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// 按照 DEFLATE 的要求，从低位开始写入比特
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, len: u32) {
+        self.buf |= (bits as u64) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.len -= 8;
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.out
+    }
+}
+
+/// LZ77 的输出，dist 为 0 时 len 是一个字面量字节，否则是一个 (长度, 距离) 匹配
+#[derive(Clone, Copy)]
+struct Symbol {
+    len: u16,
+    dist: u16,
+}
+
+fn lz77(data: &[u8]) -> Vec<Symbol> {
+    let hash = |i: usize| {
+        (((data[i] as usize) << 10) ^ ((data[i + 1] as usize) << 5) ^ data[i + 2] as usize)
+            & ((1 << HASH_BITS) - 1)
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut symbols = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max = MAX_MATCH.min(data.len() - i);
+            let mut j = head[hash(i)];
+            let mut chain = 0;
+            while j != usize::MAX && i - j <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[j..j + max]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, i - j);
+                    if len == max {
+                        break;
+                    }
+                }
+                j = prev[j];
+                chain += 1;
+            }
+        }
+        if best_len >= MIN_MATCH {
+            symbols.push(Symbol {
+                len: best_len as u16,
+                dist: best_dist as u16,
+            });
+            for k in i..i + best_len {
+                insert(k, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            symbols.push(Symbol {
+                len: data[i] as u16,
+                dist: 0,
+            });
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    symbols
+}
+
+/// 根据频率构造哈夫曼码长，码长不超过 limit
+/// 如果超过了，就将频率减半后重新构造，直到满足要求为止
+fn huffman_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let mut lengths = vec![0; freqs.len()];
+        let mut heap = BinaryHeap::new();
+        for (i, &freq) in freqs.iter().enumerate() {
+            if freq > 0 {
+                heap.push(Reverse((freq as u64, i)));
+            }
+        }
+        if heap.len() == 1 {
+            let Reverse((_, i)) = heap.pop().unwrap();
+            lengths[i] = 1;
+            return lengths;
+        }
+        // 前 freqs.len() 个节点是叶子，之后是内部节点
+        let mut parent = vec![usize::MAX; freqs.len()];
+        while heap.len() > 1 {
+            let Reverse((freq_a, a)) = heap.pop().unwrap();
+            let Reverse((freq_b, b)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(usize::MAX);
+            parent[a] = node;
+            parent[b] = node;
+            heap.push(Reverse((freq_a + freq_b, node)));
+        }
+        for (i, length) in lengths.iter_mut().enumerate() {
+            if freqs[i] > 0 {
+                let mut node = i;
+                while parent[node] != usize::MAX {
+                    node = parent[node];
+                    *length += 1;
+                }
+            }
+        }
+        if lengths.iter().all(|&a| a <= limit) {
+            return lengths;
+        }
+        for freq in freqs.iter_mut().filter(|a| **a > 0) {
+            *freq = freq.div_ceil(2);
+        }
+    }
+}
+
+/// 由码长构造范式哈夫曼编码，返回的编码已经被按位反转，可以直接交给 BitWriter
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut count = [0u32; 16];
+    for &length in lengths.iter().filter(|a| **a > 0) {
+        count[length as usize] += 1;
+    }
+    let mut next = [0u32; 16];
+    for i in 1..16 {
+        next[i] = (next[i - 1] + count[i - 1]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            code.reverse_bits() >> (32 - length as u32)
+        })
+        .collect()
+}
+
+fn length_code(len: u16) -> usize {
+    LENGTH_BASE.partition_point(|&a| a <= len) - 1
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.partition_point(|&a| a <= dist) - 1
+}
+
+/// 将码长序列以游程编码的形式转换为码长编码的符号，返回 (符号, 附加位)
+fn run_length(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut res = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|a| **a == value).count();
+        i += run;
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                res.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                res.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            res.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                res.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        res.extend(std::iter::repeat_n((value, 0), run));
+    }
+    res
+}
+
+fn write_block(w: &mut BitWriter, symbols: &[Symbol], last: bool) {
+    let mut lit_freqs = [0u32; 286];
+    let mut dist_freqs = [0u32; 30];
+    for symbol in symbols {
+        if symbol.dist == 0 {
+            lit_freqs[symbol.len as usize] += 1;
+        } else {
+            lit_freqs[257 + length_code(symbol.len)] += 1;
+            dist_freqs[dist_code(symbol.dist)] += 1;
+        }
+    }
+    lit_freqs[256] = 1;
+    let lit_lengths = huffman_lengths(&lit_freqs, 15);
+    let mut dist_lengths = huffman_lengths(&dist_freqs, 15);
+    // 即使没有任何匹配，也至少需要一个距离码
+    if dist_lengths.iter().all(|a| *a == 0) {
+        dist_lengths[0] = 1;
+    }
+    let lit_codes = canonical_codes(&lit_lengths);
+    let dist_codes = canonical_codes(&dist_lengths);
+
+    let hlit = 257.max(lit_lengths.iter().rposition(|a| *a > 0).unwrap_or(0) + 1);
+    let hdist = 1.max(dist_lengths.iter().rposition(|a| *a > 0).unwrap_or(0) + 1);
+    let rle = run_length(&[&lit_lengths[..hlit], &dist_lengths[..hdist]].concat());
+    let mut cl_freqs = [0u32; 19];
+    for (symbol, _) in &rle {
+        cl_freqs[*symbol as usize] += 1;
+    }
+    let cl_lengths = huffman_lengths(&cl_freqs, 7);
+    let cl_codes = canonical_codes(&cl_lengths);
+    let hclen = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|a| cl_lengths[*a] > 0)
+            .unwrap_or(0)
+            + 1,
+    );
+
+    w.write(last as u32, 1);
+    w.write(2, 2);
+    w.write((hlit - 257) as u32, 5);
+    w.write((hdist - 1) as u32, 5);
+    w.write((hclen - 4) as u32, 4);
+    for i in &CODE_LENGTH_ORDER[..hclen] {
+        w.write(cl_lengths[*i] as u32, 3);
+    }
+    for (symbol, extra) in rle {
+        let symbol = symbol as usize;
+        w.write(cl_codes[symbol], cl_lengths[symbol] as u32);
+        match symbol {
+            16 => w.write(extra as u32, 2),
+            17 => w.write(extra as u32, 3),
+            18 => w.write(extra as u32, 7),
+            _ => (),
+        }
+    }
+
+    for symbol in symbols {
+        if symbol.dist == 0 {
+            let lit = symbol.len as usize;
+            w.write(lit_codes[lit], lit_lengths[lit] as u32);
+        } else {
+            let code = length_code(symbol.len);
+            w.write(lit_codes[257 + code], lit_lengths[257 + code] as u32);
+            w.write(
+                (symbol.len - LENGTH_BASE[code]) as u32,
+                LENGTH_EXTRA[code] as u32,
+            );
+            let code = dist_code(symbol.dist);
+            w.write(dist_codes[code], dist_lengths[code] as u32);
+            w.write(
+                (symbol.dist - DIST_BASE[code]) as u32,
+                DIST_EXTRA[code] as u32,
+            );
+        }
+    }
+    w.write(lit_codes[256], lit_lengths[256] as u32);
+}
+
+/// 压缩为原始的 DEFLATE 数据流
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let symbols = lz77(data);
+    let mut w = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        buf: 0,
+        len: 0,
+    };
+    if symbols.is_empty() {
+        write_block(&mut w, &[], true);
+    }
+    let mut blocks = symbols.chunks(BLOCK_SYMBOLS).peekable();
+    while let Some(block) = blocks.next() {
+        write_block(&mut w, block, blocks.peek().is_none());
+    }
+    w.finish()
+}
+
+/// 压缩为 zlib 格式，它是 HTTP 中 `Content-Encoding: deflate` 的实际格式
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut res = vec![0x78, 0x9c];
+    res.append(&mut deflate(data));
+    res.extend_from_slice(&adler32(data).to_be_bytes());
+    res
+}
+
+/// 压缩为 gzip 格式，它不包含文件名和修改时间
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut res = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+    res.append(&mut deflate(data));
+    res.extend_from_slice(&crc32(data).to_le_bytes());
+    res.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    res
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, a| {
+        CRC32_TABLE[((crc ^ *a as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 是保证 b 不会溢出的最大块长度
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+    #[test]
+    fn compress() {
+        assert_eq!(zlib(b"")[..2], [0x78, 0x9c]);
+        assert!(zlib(b"").ends_with(&[0, 0, 0, 1]));
+        let data = b"Hello, Hello, Hello, Hello, World!".repeat(100);
+        let res = gzip(&data);
+        assert!(res.len() < data.len() / 10);
+        assert_eq!(res[..3], [0x1f, 0x8b, 8]);
+        assert_eq!(res[res.len() - 4..], (data.len() as u32).to_le_bytes());
+    }
+
+    /// 一个只支持动态 Huffman 块的解压器，只用于检查压缩的结果
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+    impl BitReader<'_> {
+        fn bits(&mut self, n: u8) -> usize {
+            let mut res = 0;
+            for i in 0..n {
+                res |= ((self.data[self.pos / 8] >> (self.pos % 8)) as usize & 1) << i;
+                self.pos += 1;
+            }
+            res
+        }
+        /// 范式 Huffman 码按照码长和符号依次分配，参见 RFC 1951 3.2.2
+        fn symbol(&mut self, table: &Huffman) -> usize {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for count in &table.counts[1..] {
+                code |= self.bits(1);
+                if code < first + count {
+                    return table.symbols[index + code - first];
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            panic!("invalid code")
+        }
+    }
+    /// counts: 每一种码长的码的数量，symbols: 按照码长排序的符号
+    struct Huffman {
+        counts: [usize; 16],
+        symbols: Vec<usize>,
+    }
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0; 16];
+            for a in lengths {
+                counts[*a as usize] += 1;
+            }
+            counts[0] = 0;
+            let mut symbols: Vec<usize> = (0..lengths.len()).filter(|a| lengths[*a] > 0).collect();
+            symbols.sort_by_key(|a| lengths[*a]);
+            Huffman { counts, symbols }
+        }
+    }
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        // RFC 1951 3.2.5
+        let length_base = |a: usize| match a {
+            0..=7 => a + 3,
+            28 => 258,
+            _ => ((4 + a % 4) << (a / 4 - 1)) + 3,
+        };
+        let length_extra = |a: usize| if a < 8 || a == 28 { 0 } else { a / 4 - 1 };
+        let dist_base = |a: usize| match a {
+            0..=3 => a + 1,
+            _ => ((2 + a % 2) << (a / 2 - 1)) + 1,
+        };
+        let dist_extra = |a: usize| if a < 4 { 0 } else { a / 2 - 1 };
+
+        let mut r = BitReader { data, pos: 0 };
+        let mut out: Vec<u8> = vec![];
+        loop {
+            let last = r.bits(1) == 1;
+            assert_eq!(r.bits(2), 2);
+            let hlit = r.bits(5) + 257;
+            let hdist = r.bits(5) + 1;
+            let hclen = r.bits(4) + 4;
+            let mut cl_lengths = [0; 19];
+            for i in [
+                16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+            ][..hclen]
+                .iter()
+            {
+                cl_lengths[*i] = r.bits(3) as u8;
+            }
+            let cl_table = Huffman::new(&cl_lengths);
+            let mut lengths: Vec<u8> = vec![];
+            while lengths.len() < hlit + hdist {
+                match r.symbol(&cl_table) {
+                    16 => {
+                        let prev = *lengths.last().unwrap();
+                        let n = 3 + r.bits(2);
+                        lengths.extend(std::iter::repeat_n(prev, n));
+                    }
+                    17 => {
+                        let n = 3 + r.bits(3);
+                        lengths.extend(std::iter::repeat_n(0, n));
+                    }
+                    18 => {
+                        let n = 11 + r.bits(7);
+                        lengths.extend(std::iter::repeat_n(0, n));
+                    }
+                    a => lengths.push(a as u8),
+                }
+            }
+            let lit_table = Huffman::new(&lengths[..hlit]);
+            let dist_table = Huffman::new(&lengths[hlit..]);
+            loop {
+                match r.symbol(&lit_table) {
+                    a @ 0..=255 => out.push(a as u8),
+                    256 => break,
+                    a => {
+                        let len = length_base(a - 257) + r.bits(length_extra(a - 257) as u8);
+                        let code = r.symbol(&dist_table);
+                        let dist = dist_base(code) + r.bits(dist_extra(code) as u8);
+                        assert!(dist <= 32768 && dist <= out.len());
+                        for _ in 0..len {
+                            out.push(out[out.len() - dist]);
+                        }
+                    }
+                }
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+    #[test]
+    fn round_trip() {
+        // 由 Python 的 zlib.decompress 验证过的输出
+        assert_eq!(
+            zlib(b"abcabcabcabc"),
+            [
+                120, 156, 61, 194, 49, 13, 0, 0, 0, 131, 48, 173, 27, 254, 61, 240, 145, 116, 39,
+                2, 29, 224, 4, 153
+            ]
+        );
+        // 超过 258 字节的最长匹配
+        assert_eq!(
+            zlib(&[b'a'; 300]),
+            [
+                120, 156, 237, 192, 129, 0, 0, 0, 0, 128, 32, 214, 253, 37, 86, 24, 224, 88, 38,
+                216, 168, 113, 173
+            ]
+        );
+
+        let mut seed = 1u32;
+        let mut random = |n: usize| -> Vec<u8> {
+            (0..n)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                })
+                .collect()
+        };
+        let window = random(32768);
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            // 超过 258 字节的重复需要多个匹配
+            vec![b'a'; 1000],
+            b"Hello, World! ".repeat(500),
+            // 恰好相距 32 KiB 的重复，以及超出窗口的重复
+            [&window[..], &window[..]].concat(),
+            [&window[..], &random(100)[..], &window[..1000]].concat(),
+            // 超过 BLOCK_SYMBOLS 个符号，会被分为多个块
+            random(100000),
+        ];
+        for data in inputs {
+            assert_eq!(inflate(&deflate(&data)), data);
+            let res = zlib(&data);
+            assert_eq!(inflate(&res[2..res.len() - 4]), data);
+            assert_eq!(res[res.len() - 4..], adler32(&data).to_be_bytes());
+        }
+    }
+}
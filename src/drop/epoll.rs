@@ -0,0 +1,122 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 不依赖任何外部库的 Linux epoll 封装，参见 epoll(7)
+//! 每一个被监听的文件描述符都对应一个由调用者给出的 token ，它会随事件一起被返回
+
+use std::{io, os::fd::RawFd};
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLRDHUP: u32 = 0x2000;
+/// 事件被返回一次之后，这个文件描述符就不再被监听，直到它被 modify 重新启用
+pub const EPOLLONESHOT: u32 = 1 << 30;
+
+const EPOLL_CLOEXEC: i32 = 0o2000000;
+const EPOLL_CTL_ADD: i32 = 1;
+#[allow(dead_code)]
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CTL_MOD: i32 = 3;
+
+/// 在 x86_64 上，内核中的这个结构体是紧凑排列的
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: i32) -> i32;
+    fn epoll_ctl(epfd: i32, op: i32, fd: i32, event: *mut EpollEvent) -> i32;
+    fn epoll_wait(epfd: i32, events: *mut EpollEvent, maxevents: i32, timeout: i32) -> i32;
+    fn close(fd: i32) -> i32;
+}
+
+fn cvt(a: i32) -> io::Result<i32> {
+    if a < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(a)
+    }
+}
+
+/// 一个 epoll 实例，它可以被多个线程同时使用
+pub struct Epoll(RawFd);
+impl Epoll {
+    pub fn new() -> io::Result<Self> {
+        Ok(Epoll(cvt(unsafe { epoll_create1(EPOLL_CLOEXEC) })?))
+    }
+    fn ctl(&self, op: i32, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = EpollEvent {
+            events,
+            data: token,
+        };
+        cvt(unsafe { epoll_ctl(self.0, op, fd, &mut event) }).map(|_| ())
+    }
+    /// 开始监听一个文件描述符，events 是 EPOLLIN 等标志的组合
+    pub fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_ADD, fd, token, events)
+    }
+    /// 修改一个文件描述符被监听的事件，也用于重新启用一个 EPOLLONESHOT 的文件描述符
+    pub fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_MOD, fd, token, events)
+    }
+    #[allow(dead_code)]
+    pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_DEL, fd, 0, 0)
+    }
+    /// 等待事件，最多等待 timeout 毫秒，-1 表示一直等待
+    /// 返回 (token, events) 的列表，最多有 max 个，被信号中断时返回空的列表
+    pub fn wait(&self, max: usize, timeout: i32) -> io::Result<Vec<(u64, u32)>> {
+        let mut events = vec![EpollEvent { events: 0, data: 0 }; max.max(1)];
+        let n = match cvt(unsafe {
+            epoll_wait(self.0, events.as_mut_ptr(), events.len() as i32, timeout)
+        }) {
+            Ok(a) => a as usize,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(events[..n].iter().map(|a| (a.data, a.events)).collect())
+    }
+}
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { close(self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, os::fd::AsRawFd};
+    #[test]
+    fn readable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let epoll = Epoll::new().unwrap();
+        epoll
+            .add(server.as_raw_fd(), 7, EPOLLIN | EPOLLONESHOT)
+            .unwrap();
+        assert!(epoll.wait(8, 0).unwrap().is_empty());
+
+        client.write_all(b"a").unwrap();
+        let events = epoll.wait(8, 1000).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, 7);
+        assert!(events[0].1 & EPOLLIN != 0);
+        // EPOLLONESHOT: 重新启用之前不会再次返回
+        assert!(epoll.wait(8, 0).unwrap().is_empty());
+        epoll
+            .modify(server.as_raw_fd(), 8, EPOLLIN | EPOLLONESHOT)
+            .unwrap();
+        assert_eq!(epoll.wait(8, 1000).unwrap()[0].0, 8);
+        epoll.delete(server.as_raw_fd()).unwrap();
+    }
+}
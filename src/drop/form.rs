@@ -0,0 +1,333 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 解析 HTML 表单提交的请求主体
+//! 支持 `application/x-www-form-urlencoded` 和 `multipart/form-data`
+//! 参见[此文档](https://www.rfc-editor.org/rfc/rfc7578)
+
+use std::io::{Read, Write};
+
+/// 每个部分 (part) 的头部的最大长度
+const MAX_PART_HEAD_SIZE: usize = 8192;
+
+/// 表单主体格式错误，或者在读取主体、写入临时文件时出错
+#[derive(Debug)]
+pub struct FormError;
+
+/// 上传的文件，它的内容被流式的写入了临时文件 path
+/// filename 是客户端提供的文件名，不应该被直接当作路径使用
+#[derive(Debug)]
+pub struct FormFile {
+    pub name: String,
+    pub filename: String,
+    pub content_type: String,
+    pub path: String,
+    pub size: u64,
+}
+
+/// 解析后的表单，字段和文件都保留了原有的顺序和重复的名字
+/// 临时文件会在 Form 被销毁时删除，如果需要保留，应该在此之前将其移走
+#[derive(Debug, Default)]
+pub struct Form {
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<FormFile>,
+}
+impl Drop for Form {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = std::fs::remove_file(&file.path);
+        }
+    }
+}
+
+/// 解析 `application/x-www-form-urlencoded` 主体，它的格式和查询字符串相同
+pub fn parse_urlencoded(body: &[u8]) -> Result<Form, FormError> {
+    Ok(Form {
+        fields: super::url::parse_query(std::str::from_utf8(body).map_err(|_| FormError)?),
+        files: vec![],
+    })
+}
+
+/// 从 `Content-Type` 中取出 `boundary` 参数
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (_, params) = content_type.split_once(';')?;
+    let boundary = header_params(params)
+        .into_iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))?
+        .1;
+    // 参见 RFC 2046 5.1.1
+    (!boundary.is_empty() && boundary.len() <= 70).then_some(boundary)
+}
+
+/// 解析形如 `a=1; b="x; y"` 的头部参数，参数名不区分大小写，但原样返回
+fn header_params(str: &str) -> Vec<(String, String)> {
+    let mut res = vec![];
+    let mut chars = str.chars().peekable();
+    loop {
+        while chars.next_if(|a| *a == ';' || a.is_whitespace()).is_some() {}
+        let mut k = String::new();
+        while let Some(a) = chars.next_if(|a| *a != '=' && *a != ';') {
+            k.push(a);
+        }
+        if k.is_empty() {
+            return res;
+        }
+        let mut v = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(a) = chars.next() {
+                    match a {
+                        '"' => break,
+                        '\\' => v.extend(chars.next()),
+                        a => v.push(a),
+                    }
+                }
+            }
+            while let Some(a) = chars.next_if(|a| *a != ';') {
+                v.push(a);
+            }
+        }
+        res.push((k.trim().to_owned(), v.trim().to_owned()));
+    }
+}
+
+/// 解析 `multipart/form-data` 主体
+/// 文件部分会被一边读取一边写入 temp_dir 中的临时文件，所以上传大文件并不会占用大量内存
+pub fn parse_multipart<R: Read>(
+    reader: R,
+    boundary: &str,
+    temp_dir: &str,
+) -> Result<Form, FormError> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // 第一个分隔符前面没有换行，补上它就可以用同样的方式寻找所有分隔符
+    let mut buf = PartReader {
+        inner: reader,
+        buf: b"\r\n".to_vec(),
+        eof: false,
+    };
+    let mut form = Form::default();
+
+    // 忽略第一个分隔符之前的前言 (preamble)
+    buf.read_until(&delimiter, |_| Ok(()))?;
+    loop {
+        buf.fill_to(2)?;
+        // 最后一个分隔符以 `--` 结尾，之后的结语 (epilogue) 被忽略
+        if buf.buf.starts_with(b"--") {
+            return Ok(form);
+        }
+        // 分隔符之后可以有空白 (transport-padding)
+        buf.read_until(b"\r\n", |a| {
+            match a.iter().all(|a| *a == b' ' || *a == b'\t') {
+                true => Ok(()),
+                false => Err(FormError),
+            }
+        })?;
+
+        let mut head = vec![];
+        buf.fill_to(2)?;
+        if buf.buf.starts_with(b"\r\n") {
+            buf.buf.drain(..2);
+        } else {
+            buf.read_until(b"\r\n\r\n", |a| {
+                head.extend_from_slice(a);
+                match head.len() > MAX_PART_HEAD_SIZE {
+                    true => Err(FormError),
+                    false => Ok(()),
+                }
+            })?;
+        }
+        let (name, filename, content_type) = parse_part_head(&head)?;
+
+        if let Some(filename) = filename {
+            let (path, mut file) = create_temp_file(temp_dir)?;
+            // 先登记文件，这样即使之后出错，它也会随着 Form 被删除
+            form.files.push(FormFile {
+                name,
+                filename,
+                content_type: content_type.unwrap_or("application/octet-stream".to_owned()),
+                path,
+                size: 0,
+            });
+            let size = &mut form.files.last_mut().unwrap().size;
+            buf.read_until(&delimiter, |a| {
+                *size += a.len() as u64;
+                file.write_all(a).map_err(|_| FormError)
+            })?;
+        } else {
+            let mut value = vec![];
+            buf.read_until(&delimiter, |a| {
+                value.extend_from_slice(a);
+                Ok(())
+            })?;
+            form.fields
+                .push((name, String::from_utf8_lossy(&value).into_owned()));
+        }
+    }
+}
+
+/// 从部分的头部中取出 (字段名, 文件名, Content-Type)
+fn parse_part_head(head: &[u8]) -> Result<(String, Option<String>, Option<String>), FormError> {
+    let head = String::from_utf8_lossy(head);
+    let mut disposition = None;
+    let mut content_type = None;
+    for line in head.split("\r\n") {
+        let (k, v) = line.split_once(':').ok_or(FormError)?;
+        if k.trim().eq_ignore_ascii_case("Content-Disposition") {
+            disposition = Some(v.trim());
+        } else if k.trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(v.trim().to_owned());
+        }
+    }
+    let (kind, params) = disposition
+        .ok_or(FormError)?
+        .split_once(';')
+        .ok_or(FormError)?;
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return Err(FormError);
+    }
+    let params = header_params(params);
+    let get = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.to_owned())
+    };
+    // 一些客户端会发送完整的本地路径，只保留最后一段
+    let filename =
+        get("filename").map(|a| a.rsplit(['/', '\\']).next().unwrap_or_default().to_owned());
+    Ok((get("name").ok_or(FormError)?, filename, content_type))
+}
+
+/// 在 temp_dir 中创建一个名字随机的新文件
+fn create_temp_file(temp_dir: &str) -> Result<(String, std::fs::File), FormError> {
+    std::fs::create_dir_all(temp_dir).map_err(|_| FormError)?;
+    // 同一时刻可能有多个线程在创建临时文件，所以加入一个递增的序号
+    static SEQ: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+    let mut random = super::random::random_init(
+        (u32::from(super::time::Time::nsec().unwrap_or(0)) << 16)
+            ^ SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            ^ std::process::id(),
+    );
+    for _ in 0..16 {
+        let path = format!("{}/upload-{:016x}", temp_dir, random.next_u64());
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(_) => break,
+        }
+    }
+    Err(FormError)
+}
+
+/// 带有回看缓冲区的读取器，用于寻找可能跨越两次读取的分隔符
+struct PartReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+impl<R: Read> PartReader<R> {
+    /// 读取更多的数据，返回值说明了是否读到了数据
+    fn fill(&mut self) -> Result<bool, FormError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut chunk = [0; 8192];
+        let n = loop {
+            match self.inner.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(FormError),
+            }
+        };
+        self.eof = n == 0;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n != 0)
+    }
+    /// 确保缓冲区中至少有 n 个字节
+    fn fill_to(&mut self, n: usize) -> Result<(), FormError> {
+        while self.buf.len() < n {
+            if !self.fill()? {
+                return Err(FormError);
+            }
+        }
+        Ok(())
+    }
+    /// 将 pat 之前的数据一块一块的交给 out ，并消耗掉 pat
+    /// 如果在找到 pat 之前就读到了末尾，则返回错误
+    fn read_until(
+        &mut self,
+        pat: &[u8],
+        mut out: impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        loop {
+            if let Some(i) = self.buf.windows(pat.len()).position(|a| a == pat) {
+                out(&self.buf[..i])?;
+                self.buf.drain(..i + pat.len());
+                return Ok(());
+            }
+            // 缓冲区的末尾可能是 pat 的前一部分，必须保留
+            let n = self.buf.len().saturating_sub(pat.len() - 1);
+            if n > 0 {
+                out(&self.buf[..n])?;
+                self.buf.drain(..n);
+            }
+            if !self.fill()? {
+                return Err(FormError);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn multipart() {
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=\"a b\"").unwrap(),
+            "a b"
+        );
+        let body = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            你好\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"f\"; filename=\"C:\\\\a;b.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line\r\n--Xy\r\n--XyZ--\r\nepilogue";
+        let dir = std::env::temp_dir().join("ttweb-form-test");
+        let dir = dir.to_str().unwrap();
+        // 每次只读取一个字节，以检查跨越多次读取的分隔符
+        struct OneByte<'a>(&'a [u8]);
+        impl Read for OneByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.take(1).read(buf).inspect(|n| self.0 = &self.0[*n..])
+            }
+        }
+        let form = parse_multipart(OneByte(body.as_bytes()), "XyZ", dir).unwrap();
+        assert_eq!(form.fields, vec![("title".to_owned(), "你好".to_owned())]);
+        let file = &form.files[0];
+        assert_eq!(
+            (file.name.as_str(), file.filename.as_str(), file.size),
+            ("f", "a;b.txt", 10)
+        );
+        assert_eq!(std::fs::read(&file.path).unwrap(), b"line\r\n--Xy");
+        let path = file.path.clone();
+        drop(form);
+        assert!(!std::path::Path::new(&path).exists());
+
+        assert!(parse_multipart(&b"--XyZ\r\n\r\nno name\r\n--XyZ--"[..], "XyZ", dir).is_err());
+        assert!(parse_multipart(&b"--XyZ\r\n"[..], "XyZ", dir).is_err());
+        assert_eq!(
+            parse_urlencoded(b"a=1&b=%E4%BD%A0+x").unwrap().fields[1],
+            ("b".to_owned(), "你 x".to_owned())
+        );
+    }
+}
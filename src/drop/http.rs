@@ -5,7 +5,174 @@
  * along with this program;
  * if not, see <https://www.gnu.org/licenses/>.
  */
-use std::{collections::HashMap, time::SystemTimeError};
+use std::{
+    io::{BufRead, Read, Write},
+    sync::{Arc, Mutex},
+    time::SystemTimeError,
+};
+
+use super::form::{multipart_boundary, parse_multipart, parse_urlencoded, Form, FormError};
+
+/// 请求头或响应头的集合，请求和响应都使用它
+///
+/// 键不区分大小写，但会保留第一次写入时的大小写用于输出
+/// 同一个键可以有多个值，例如多个 `Set-Cookie` ，它们按照写入的顺序被保存和输出
+/// 值两端的空白会被去掉
+#[derive(Clone, Default, Debug)]
+pub struct HttpHeaders {
+    entries: Vec<(String, String)>,
+}
+impl HttpHeaders {
+    pub fn new() -> Self {
+        HttpHeaders { entries: vec![] }
+    }
+    /// 返回键为 k 的第一个值
+    pub fn get(&self, k: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(a, _)| a.eq_ignore_ascii_case(k))
+            .map(|(_, v)| v)
+    }
+    /// 按照顺序返回键为 k 的所有值
+    pub fn get_all<'a>(&'a self, k: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(a, _)| a.eq_ignore_ascii_case(k))
+            .map(|(_, v)| v)
+    }
+    pub fn contains_key(&self, k: &str) -> bool {
+        self.get(k).is_some()
+    }
+    /// 设置键为 k 的值，它会替换掉所有已有的值，并返回其中的第一个
+    /// 被替换的键仍然处于原来的位置
+    pub fn insert(&mut self, k: &str, v: String) -> Option<String> {
+        let v = v.trim().to_owned();
+        match self
+            .entries
+            .iter()
+            .position(|(a, _)| a.eq_ignore_ascii_case(k))
+        {
+            Some(i) => {
+                let old = std::mem::replace(&mut self.entries[i].1, v);
+                let mut j = 0;
+                self.entries.retain(|(a, _)| {
+                    j += 1;
+                    j - 1 <= i || !a.eq_ignore_ascii_case(k)
+                });
+                Some(old)
+            }
+            None => {
+                self.entries.push((k.to_owned(), v));
+                None
+            }
+        }
+    }
+    /// 追加一个值，不影响已有的值
+    pub fn append(&mut self, k: &str, v: String) {
+        self.entries.push((k.to_owned(), v.trim().to_owned()));
+    }
+    /// 删除键为 k 的所有值，并返回其中的第一个
+    pub fn remove(&mut self, k: &str) -> Option<String> {
+        let old = self.get(k).cloned();
+        self.entries.retain(|(a, _)| !a.eq_ignore_ascii_case(k));
+        old
+    }
+    /// 按照写入的顺序遍历所有的 (键, 值)
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.entries.iter()
+    }
+}
+
+/// 严格解析模式下，请求被拒绝的原因
+/// state: 应该返回的状态，例如 `400 BAD REQUEST`
+/// reason: 用于日志的说明
+#[derive(Debug, PartialEq)]
+pub struct HttpStrictError {
+    pub state: &'static str,
+    pub reason: &'static str,
+}
+impl HttpStrictError {
+    fn bad_request(reason: &'static str) -> Self {
+        HttpStrictError {
+            state: "400 BAD REQUEST",
+            reason,
+        }
+    }
+}
+
+/// token 中允许的字符，参见 RFC 9110 5.6.2
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+/// 按照 RFC 9112 严格的检查请求行与请求头的语法
+/// str 是以 CRLF 分隔的请求行与请求头，不包含结尾的空行
+///
+/// 宽松的解析可能使 ttweb 与其前后的代理对同一个请求有不同的理解，从而导致请求走私 (request smuggling)
+/// 所以任何有歧义的请求都会被拒绝，而不是被尽力的理解
+pub fn check_strict(str: &str) -> Result<(), HttpStrictError> {
+    let mut lines = str.split("\r\n").filter(|a| !a.is_empty());
+    if str.split("\r\n").any(|a| a.contains('\r')) {
+        return Err(HttpStrictError::bad_request("bare CR in the request head"));
+    }
+
+    // 请求行，只能由单个空格分隔，参见 RFC 9112 3
+    let parts: Vec<&str> = lines.next().unwrap_or("").split(' ').collect();
+    let (method, target, version) = match parts[..] {
+        [a, b, c] => (a, b, c),
+        _ => return Err(HttpStrictError::bad_request("malformed request line")),
+    };
+    if method.is_empty() || !method.bytes().all(is_tchar) {
+        return Err(HttpStrictError::bad_request("invalid method"));
+    }
+    if !target.bytes().all(|a| a.is_ascii_graphic())
+        || !(target.starts_with('/')
+            || target.contains("://")
+            || (target == "*" && method == "OPTIONS")
+            || method == "CONNECT")
+    {
+        return Err(HttpStrictError::bad_request("invalid request target"));
+    }
+    match version.as_bytes() {
+        b"HTTP/1.0" | b"HTTP/1.1" => (),
+        [b'H', b'T', b'T', b'P', b'/', a, b'.', b] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            return Err(HttpStrictError {
+                state: "505 HTTP VERSION NOT SUPPORTED",
+                reason: "unsupported HTTP version",
+            })
+        }
+        _ => return Err(HttpStrictError::bad_request("malformed HTTP version")),
+    }
+
+    // 请求头，参见 RFC 9112 5
+    let mut hosts = 0;
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            return Err(HttpStrictError::bad_request("obsolete line folding"));
+        }
+        let (k, v) = match line.split_once(':') {
+            Some(a) => a,
+            None => return Err(HttpStrictError::bad_request("header without a colon")),
+        };
+        // 键与冒号之间不能有空白
+        if k.is_empty() || !k.bytes().all(is_tchar) {
+            return Err(HttpStrictError::bad_request("invalid header name"));
+        }
+        if v.bytes().any(|a| (a < 0x20 && a != b'\t') || a == 0x7f) {
+            return Err(HttpStrictError::bad_request("invalid header value"));
+        }
+        if k.eq_ignore_ascii_case("Host") {
+            hosts += 1;
+        }
+    }
+    // HTTP/1.1 的请求必须有且只有一个 Host ，参见 RFC 9112 3.2
+    if version == "HTTP/1.1" && hosts != 1 {
+        return Err(HttpStrictError::bad_request(
+            "missing or duplicate Host header",
+        ));
+    }
+    Ok(())
+}
 
 /// 这个错误运用于一切可能的错误情况
 /// 并不需要定义成枚举，因为该错误表示的意思是可以确定的
@@ -14,12 +181,18 @@ pub struct HttpRequestError;
 /// 可以解析任意标准的 HTTP 请求字符串
 ///
 /// request_method: 请求方法, 可能性有 `GET`, `HEAD`, `POST`, 'PUT' 等
-/// url: 请求希望获取的页面的链接
+/// url: 请求希望获取的页面的链接，即原始的请求目标
+/// path: 从 url 中解析出的，被解码和规范化的路径，路由应该使用它而非 url
+/// segments: 被解码的路径段，参见 drop::url::path_segments
+/// query: 从 url 中解析出的查询字符串中的键值对，保留原有的顺序和重复的键
 /// version: HTTP 协议的版本，例如 `1.1`
-/// headers: 该哈希表的键表示请求头的键，值表示请求头的值
-/// content: 可选的，请求的主体部分，以缓冲区的方式储存
+/// headers: 所有的请求头，参见 HttpHeaders
+/// content: 可选的，请求的主体部分，是一个恰好只能读取完整个主体的读取器
 ///
-/// content 以缓冲区的方式储存的目的是避免过大的主体造成的一次性内存读取从而拖慢效率
+/// content 以读取器的方式储存的目的是避免过大的主体造成的一次性内存读取从而拖慢效率
+/// 主体可能由 `Content-Length` 限定长度，也可能使用了分块传输编码 (chunked)，参见 HttpContent
+/// 如果需要一次性取得整个主体，可以使用 content_bytes 函数
+/// T 是连接上的缓冲读取器，在持久连接中它被多个请求依次借用
 ///
 /// 一个请求头的例子: `Content-Length: 32`，`Content-Length` 是键，`32` 是值
 ///
@@ -27,17 +200,23 @@ pub struct HttpRequestError;
 pub struct HttpRequest<'a, T> {
     request_method: String,
     url: String,
+    path: String,
+    segments: Vec<String>,
+    query: Vec<(String, String)>,
     version: String,
-    headers: HashMap<String, String>,
-    content: Option<std::iter::Take<std::io::Lines<std::io::BufReader<&'a mut T>>>>,
+    headers: HttpHeaders,
+    content: Option<HttpContent<'a, T>>,
 }
 impl<'a, T> HttpRequest<'a, T> {
     pub fn new() -> Self {
         HttpRequest {
             request_method: String::new(),
             url: String::new(),
+            path: String::new(),
+            segments: vec![],
+            query: vec![],
             version: String::new(),
-            headers: HashMap::new(),
+            headers: HttpHeaders::new(),
             content: None,
         }
     }
@@ -64,6 +243,14 @@ impl<'a, T> HttpRequest<'a, T> {
             Some(a) => request.url = a.to_string(),
             _ => return Err(HttpRequestError),
         }
+        match super::url::parse_target(&request.url) {
+            Some((path, segments, query)) => {
+                request.query = super::url::parse_query(query);
+                request.path = path;
+                request.segments = segments;
+            }
+            _ => return Err(HttpRequestError),
+        }
         match req_line.next() {
             Some(a) => request.version = a.to_string(),
             _ => return Err(HttpRequestError),
@@ -80,39 +267,721 @@ impl<'a, T> HttpRequest<'a, T> {
                     }
                 },
             };
-            request.headers.insert(k.to_string(), v.to_string());
+            request.headers.append(k, v.to_string());
         }
         Ok(request)
     }
-    pub fn get_header(&self, str: String) -> Option<&String> {
-        self.headers.get(&str)
+    /// 返回键为 k 的第一个请求头的值，k 不区分大小写
+    pub fn get_header(&self, k: &str) -> Option<&String> {
+        self.headers.get(k)
+    }
+    /// 所有的请求头，用于读取同名的多个请求头，例如 WebSocket 握手中的 `Connection`
+    #[cfg_attr(feature = "no-glisp", allow(dead_code))]
+    pub fn headers(&self) -> &HttpHeaders {
+        &self.headers
     }
-    #[allow(dead_code)]
     pub fn request_method(&self) -> &String {
         &self.request_method
     }
     pub fn url(&self) -> &String {
         &self.url
     }
-    #[allow(dead_code)]
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+    pub fn segments(&self) -> &Vec<String> {
+        &self.segments
+    }
+    pub fn query(&self) -> &Vec<(String, String)> {
+        &self.query
+    }
+    /// 所有 `Cookie` 请求头中的 Cookie ，参见 parse_cookies
+    #[cfg_attr(feature = "no-glisp", allow(dead_code))]
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(|a| parse_cookies(a))
+            .collect()
+    }
     pub fn version(&self) -> &String {
         &self.version
     }
+    /// 严格的检查决定请求主体长度的 `Content-Length` 和 `Transfer-Encoding` ，参见 RFC 9112 6
+    /// 两者同时存在、`Content-Length` 有多个不同的值、最后的传输编码不是 chunked 等情况都会被拒绝
+    /// 返回 `Content-Length` 规范化之后的值，例如 `5, 5` 会被规范化为 5
+    pub fn check_framing(&self) -> Result<Option<u64>, HttpStrictError> {
+        let mut length = None;
+        for value in self
+            .headers
+            .get_all("Content-Length")
+            .flat_map(|a| a.split(','))
+        {
+            let value = value.trim();
+            let parsed = match value.parse::<u64>() {
+                Ok(a) if value.bytes().all(|a| a.is_ascii_digit()) => a,
+                _ => return Err(HttpStrictError::bad_request("invalid Content-Length")),
+            };
+            if length.is_some_and(|a| a != parsed) {
+                return Err(HttpStrictError::bad_request("invalid Content-Length"));
+            }
+            length = Some(parsed);
+        }
+
+        let codings: Vec<String> = self
+            .headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|a| a.split(','))
+            .map(|a| a.trim().to_ascii_lowercase())
+            .collect();
+        if codings.is_empty() {
+            return Ok(length);
+        }
+        if self.version == "HTTP/1.0" {
+            return Err(HttpStrictError::bad_request(
+                "Transfer-Encoding in an HTTP/1.0 request",
+            ));
+        }
+        if length.is_some() {
+            return Err(HttpStrictError::bad_request(
+                "both Transfer-Encoding and Content-Length",
+            ));
+        }
+        if codings.last().map(|a| a.as_str()) != Some("chunked")
+            || codings.iter().filter(|a| *a == "chunked").count() != 1
+        {
+            return Err(HttpStrictError::bad_request(
+                "chunked is not the final transfer coding",
+            ));
+        }
+        if codings.len() != 1 {
+            return Err(HttpStrictError {
+                state: "501 NOT IMPLEMENTED",
+                reason: "unsupported transfer coding",
+            });
+        }
+        Ok(None)
+    }
+    /// 根据 HTTP 版本和 `Connection` 请求头判断客户端是否希望保持连接
+    /// HTTP/1.1 默认保持连接，除非声明了 `close`
+    /// HTTP/1.0 默认不保持连接，除非声明了 `keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|v| v.split(','))
+                .any(|e| e.trim().eq_ignore_ascii_case(token))
+        };
+        if has_token("close") {
+            false
+        } else if self.version == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            self.version == "HTTP/1.1"
+        }
+    }
 
-    pub fn set_content(
-        &mut self,
-        content: Option<std::iter::Take<std::io::Lines<std::io::BufReader<&'a mut T>>>>,
-    ) {
+    pub fn set_content(&mut self, content: Option<HttpContent<'a, T>>) {
         self.content = content;
     }
 }
+impl<'a, T: BufRead> HttpRequest<'a, T> {
+    /// 一次性读取请求主体中剩余的全部字节
+    /// 如果没有主体，或者主体在读取完毕之前中断或格式错误，则返回 None
+    #[cfg_attr(feature = "no-glisp", allow(dead_code))]
+    pub fn content_bytes(&mut self) -> Option<Vec<u8>> {
+        let content = self.content.as_mut()?;
+        let mut buf = Vec::with_capacity(match content {
+            HttpContent::Length(a) => a.limit().try_into().unwrap_or(0),
+            HttpContent::Chunked(_) => 0,
+        });
+        match content.read_to_end(&mut buf) {
+            Ok(_) if content.is_finished() => Some(buf),
+            _ => None,
+        }
+    }
+    /// 按照 `Content-Type` 读取并解析表单主体，参见 form 模块
+    /// multipart/form-data 中上传的文件会被流式的写入 temp_dir 目录
+    /// 如果请求没有主体，或者主体不是表单，则返回 Ok(None)
+    #[cfg_attr(feature = "no-glisp", allow(dead_code))]
+    pub fn content_form(&mut self, temp_dir: &str) -> Result<Option<Form>, FormError> {
+        let content_type = match (&self.content, self.get_header("Content-Type")) {
+            (Some(_), Some(a)) => a.to_owned(),
+            _ => return Ok(None),
+        };
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            parse_urlencoded(&self.content_bytes().ok_or(FormError)?).map(Some)
+        } else if mime.eq_ignore_ascii_case("multipart/form-data") {
+            let boundary = multipart_boundary(&content_type).ok_or(FormError)?;
+            let content = self.content.as_mut().ok_or(FormError)?;
+            parse_multipart(content, &boundary, temp_dir).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+    /// 请求主体是否超过了长度限制，参见 ChunkedReader
+    /// 由 `Content-Length` 限定长度的主体应该在读取之前就被检查
+    pub fn content_too_large(&self) -> bool {
+        self.content.as_ref().is_some_and(|a| a.is_too_large())
+    }
+    /// 丢弃请求主体中未被读取的部分
+    /// 在持久连接中，必须这样做才能找到下一个请求的开头
+    /// 返回值说明了主体是否被完整的读取了
+    pub fn discard_content(&mut self) -> bool {
+        if let Some(content) = &mut self.content {
+            if std::io::copy(content, &mut std::io::sink()).is_err() {
+                return false;
+            }
+            return content.is_finished();
+        }
+        true
+    }
+}
+
+/// 请求主体的读取器
+/// Length: 由 `Content-Length` 限定长度的主体
+/// Chunked: 使用分块传输编码 (`Transfer-Encoding: chunked`) 的主体
+///
+/// 无论哪一种，读取器都只会读到主体的末尾为止，不会越过主体读到下一个请求
+pub enum HttpContent<'a, T> {
+    Length(std::io::Take<&'a mut T>),
+    Chunked(ChunkedReader<&'a mut T>),
+}
+impl<T: BufRead> HttpContent<'_, T> {
+    /// 主体是否已经被完整的读取了
+    pub fn is_finished(&self) -> bool {
+        match self {
+            HttpContent::Length(a) => a.limit() == 0,
+            HttpContent::Chunked(a) => a.finished,
+        }
+    }
+    /// 主体是否因为超过了长度限制而无法被完整的读取
+    pub fn is_too_large(&self) -> bool {
+        match self {
+            HttpContent::Length(_) => false,
+            HttpContent::Chunked(a) => a.too_large,
+        }
+    }
+}
+impl<T: BufRead> Read for HttpContent<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            HttpContent::Length(a) => a.read(buf),
+            HttpContent::Chunked(a) => a.read(buf),
+        }
+    }
+}
+
+/// 分块传输编码的解码器，参见 RFC 9112 7.1
+///
+/// 每个块的格式是 `<十六进制长度>[;扩展]\r\n<数据>\r\n` ，长度为 0 的块表示主体结束
+/// 最后一个块之后可以跟随若干尾部字段，然后以空行结束
+/// 块扩展会被忽略，尾部字段会被保存在 trailers 中
+///
+/// limit: 解码后的主体最多允许的字节数，超过它时读取会失败，并且 too_large 会被设置
+/// 块长度所在的行，以及尾部字段的数量和总字节数同样有各自的限制，参见 limit_lines
+pub struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    limit: u64,
+    max_line: u64,
+    max_trailers: usize,
+    max_trailer_size: u64,
+    finished: bool,
+    pub too_large: bool,
+    pub trailers: Vec<(String, String)>,
+}
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn with_limit(inner: R, limit: u64) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            limit,
+            max_line: 8192,
+            max_trailers: 100,
+            max_trailer_size: 16384,
+            finished: false,
+            too_large: false,
+            trailers: vec![],
+        }
+    }
+    /// max_line: 块长度所在的行（包括块扩展）最多允许的字节数
+    /// max_trailers, max_trailer_size: 尾部字段最多允许的数量和总字节数
+    /// 超过它们时读取同样会失败，并且 too_large 会被设置
+    pub fn limit_lines(
+        mut self,
+        max_line: u64,
+        max_trailers: usize,
+        max_trailer_size: u64,
+    ) -> Self {
+        self.max_line = max_line;
+        self.max_trailers = max_trailers;
+        self.max_trailer_size = max_trailer_size;
+        self
+    }
+    fn exceed<T>(&mut self) -> std::io::Result<T> {
+        self.too_large = true;
+        Err(std::io::ErrorKind::FileTooLarge.into())
+    }
+    /// 读取一行，它（包括行尾）最多有 max 个字节，读取到限制之外的一个字节就可以知道是否超出了限制
+    /// 返回去掉行尾的行和它原本的字节数
+    fn read_line(&mut self, max: u64) -> std::io::Result<(String, u64)> {
+        let mut line = String::new();
+        let n = (&mut self.inner)
+            .take(max.saturating_add(1))
+            .read_line(&mut line)? as u64;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        if n > max {
+            return self.exceed();
+        }
+        Ok((line.trim_end_matches(['\r', '\n']).to_owned(), n))
+    }
+    fn read_chunk_size(&mut self) -> std::io::Result<()> {
+        let (line, _) = self.read_line(self.max_line)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        self.remaining = match u64::from_str_radix(size, 16) {
+            Ok(a) if !size.starts_with('+') => a,
+            _ => return Err(std::io::ErrorKind::InvalidData.into()),
+        };
+        if self.remaining > self.limit {
+            return self.exceed();
+        }
+        self.limit -= self.remaining;
+        if self.remaining == 0 {
+            let mut trailer_size = 0;
+            loop {
+                let (line, n) = self.read_line(self.max_trailer_size - trailer_size)?;
+                if line.is_empty() {
+                    break;
+                }
+                trailer_size += n;
+                if self.trailers.len() >= self.max_trailers {
+                    return self.exceed();
+                }
+                match line.split_once(':') {
+                    Some((k, v)) => self.trailers.push((k.to_owned(), v.trim().to_owned())),
+                    _ => return Err(std::io::ErrorKind::InvalidData.into()),
+                }
+            }
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.read_chunk_size()?;
+            if self.finished {
+                return Ok(0);
+            }
+        }
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            let mut line = String::new();
+            (&mut self.inner).take(2).read_line(&mut line)?;
+            if line != "\r\n" && line != "\n" {
+                return Err(std::io::ErrorKind::InvalidData.into());
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// 分块传输编码的编码器，每次 write 都会被写成一个块
+/// 写完之后必须调用 finish 来写出最后一个长度为 0 的块
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner }
+    }
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // 长度为 0 的块意味着主体的结束，所以不能写出空块
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner
+            .write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 解析 `Range` 请求头时可能出现的错误
+/// Invalid: 无法解析或不是字节范围，按照 RFC 9110 14.2 ，这时应该忽略该请求头
+/// Unsatisfiable: 没有任何一个范围与文件重叠，这时应该返回 `416 Range Not Satisfiable`
+#[derive(Debug, PartialEq)]
+pub enum RangeError {
+    Invalid,
+    Unsatisfiable,
+}
+
+/// 解析形如 `bytes=0-499, 1000-, -500` 的 `Range` 请求头，参见 RFC 9110 14.1.2
+/// len: 被请求的内容的总长度
+///
+/// 返回的每一个范围都是闭区间 (first, last) ，并且已经被裁剪到 len 之内
+/// 超出 len 的范围会被丢弃，但只要有一个范围是可以满足的，就不会返回 Unsatisfiable
+pub fn parse_range(str: &str, len: u64) -> Result<Vec<(u64, u64)>, RangeError> {
+    let (unit, ranges) = str.split_once('=').ok_or(RangeError::Invalid)?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Err(RangeError::Invalid);
+    }
+    let mut res = vec![];
+    for range in ranges
+        .split(',')
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+    {
+        let (first, last) = range.split_once('-').ok_or(RangeError::Invalid)?;
+        let parse = |a: &str| match a.bytes().all(|b| b.is_ascii_digit()) {
+            true => a.parse::<u64>().map_err(|_| RangeError::Invalid),
+            false => Err(RangeError::Invalid),
+        };
+        let range = if first.is_empty() {
+            // 后缀范围，例如 `-500` 表示最后 500 个字节
+            let suffix = parse(last)?;
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        } else {
+            let first = parse(first)?;
+            let last = if last.is_empty() {
+                u64::MAX
+            } else {
+                parse(last)?
+            };
+            if last < first {
+                return Err(RangeError::Invalid);
+            }
+            if first >= len {
+                continue;
+            }
+            (first, last.min(len - 1))
+        };
+        res.push(range);
+    }
+    if res.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+    Ok(res)
+}
+
+/// 根据 `Accept-Encoding` 请求头，从 supported 中选择一个内容编码，参见 RFC 9110 12.5.3
+/// supported: 服务器支持的编码，q 值相同时靠前的优先
+///
+/// 没有被列出的编码只有在存在 `*` 时才是可接受的，q 值为 0 的编码是不可接受的
+/// 如果没有可接受的编码，则返回 None ，这时应该不编码地发送内容
+pub fn negotiate_encoding(accept: &str, supported: &[&'static str]) -> Option<&'static str> {
+    let mut qvalues = vec![];
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|a| a.trim().split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, v)| v.trim().parse::<f32>().ok())
+            .unwrap_or(0.0);
+        // x-gzip 是 gzip 的别名，参见 RFC 9110 8.4.1.3
+        let coding = if coding == "x-gzip" {
+            "gzip".to_owned()
+        } else {
+            coding
+        };
+        qvalues.push((coding, q));
+    }
+    let qvalue = |coding: &str| {
+        qvalues
+            .iter()
+            .find(|(a, _)| a == coding)
+            .or_else(|| qvalues.iter().find(|(a, _)| a == "*"))
+            .map_or(0.0, |(_, q)| *q)
+    };
+    let mut best = None;
+    let mut best_q = 0.0;
+    for coding in supported {
+        let q = qvalue(coding);
+        if q > best_q {
+            best = Some(*coding);
+            best_q = q;
+        }
+    }
+    best
+}
+
+/// 解析 `Cookie` 请求头，例如 `a=1; b="2"` ，保留原有的顺序和重复的名字
+/// 值两端的双引号会被去掉，没有 `=` 的项会被忽略，参见 RFC 6265 5.4
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub fn parse_cookies(str: &str) -> Vec<(String, String)> {
+    str.split(';')
+        .filter_map(|a| {
+            let (k, v) = a.split_once('=')?;
+            let v = v.trim();
+            let v = match v.strip_prefix('"').and_then(|a| a.strip_suffix('"')) {
+                Some(a) => a,
+                None => v,
+            };
+            (!k.trim().is_empty()).then(|| (k.trim().to_owned(), v.to_owned()))
+        })
+        .collect()
+}
+
+/// Cookie 的值中允许的字符，参见 RFC 6265 4.1.1
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+fn is_cookie_octet(c: u8) -> bool {
+    matches!(c, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+/// Cookie 的 `SameSite` 属性
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// 构造一个 `Set-Cookie` 响应头，参见[此文档](https://www.rfc-editor.org/rfc/rfc6265)
+///
+/// 例如 `SetCookie::new("sid", "abc").path("/").max_age(3600).http_only(true)`
+/// expires 是以秒为单位的时间戳，max_age 为 0 或负数时，浏览器会立即删除这个 Cookie
+/// 浏览器不接受没有 `Secure` 的 `SameSite=None` ，所以这时会自动加上 `Secure`
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<i64>,
+    expires: Option<u64>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        SetCookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            ..Default::default()
+        }
+    }
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+    pub fn max_age(mut self, secs: i64) -> Self {
+        self.max_age = Some(secs);
+        self
+    }
+    pub fn expires(mut self, timestamp: u64) -> Self {
+        self.expires = Some(timestamp);
+        self
+    }
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+    /// 按照 `Set-Cookie` 中属性的写法设置属性，例如 `Path=/; Max-Age=3600; HttpOnly`
+    /// 属性名不区分大小写，如果有无法识别的属性或者格式错误的值，则返回 None
+    pub fn attributes(mut self, str: &str) -> Option<Self> {
+        for item in str.split(';').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            let (k, v) = match item.split_once('=') {
+                Some((k, v)) => (k.trim().to_ascii_lowercase(), Some(v.trim())),
+                None => (item.to_ascii_lowercase(), None),
+            };
+            self = match (k.as_str(), v) {
+                ("path", Some(v)) => self.path(v),
+                ("domain", Some(v)) => self.domain(v),
+                ("max-age", Some(v)) => self.max_age(v.parse().ok()?),
+                ("expires", Some(v)) => self.expires(super::time::parse_http_date(v)?),
+                ("secure", None) => self.secure(true),
+                ("httponly", None) => self.http_only(true),
+                ("samesite", Some(v)) => self.same_site(match v.to_ascii_lowercase().as_str() {
+                    "strict" => SameSite::Strict,
+                    "lax" => SameSite::Lax,
+                    "none" => SameSite::None,
+                    _ => return None,
+                }),
+                _ => return None,
+            }
+        }
+        Some(self)
+    }
+    /// 生成 `Set-Cookie` 的值
+    /// 如果名字不是 token ，值中有不允许的字符，或者属性中有控制字符和 `;` ，则返回 None
+    pub fn to_header(&self) -> Option<String> {
+        let value = match self
+            .value
+            .strip_prefix('"')
+            .and_then(|a| a.strip_suffix('"'))
+        {
+            Some(a) => a,
+            None => &self.value,
+        };
+        let valid_attribute = |a: &str| !a.bytes().any(|a| a.is_ascii_control() || a == b';');
+        if self.name.is_empty()
+            || !self.name.bytes().all(is_tchar)
+            || !value.bytes().all(is_cookie_octet)
+            || !self
+                .path
+                .iter()
+                .chain(&self.domain)
+                .all(|a| valid_attribute(a))
+        {
+            return None;
+        }
+
+        let mut res = format!("{}={}", self.name, self.value);
+        if let Some(a) = &self.path {
+            res += &format!("; Path={}", a);
+        }
+        if let Some(a) = &self.domain {
+            res += &format!("; Domain={}", a);
+        }
+        if let Some(a) = self.max_age {
+            res += &format!("; Max-Age={}", a);
+        }
+        if let Some(a) = self.expires {
+            res += &format!(
+                "; Expires={}",
+                super::time::Time::from_timestamp(a).to_http_date().ok()?
+            );
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            res += "; Secure";
+        }
+        if self.http_only {
+            res += "; HttpOnly";
+        }
+        if let Some(a) = self.same_site {
+            res += &format!("; SameSite={:?}", a);
+        }
+        Some(res)
+    }
+}
+
+/// 所有已注册的状态码及其原因短语，参见 RFC 9110 15 ，以及 RFC 6585 、RFC 4918 等
+const STATUS_CODES: [(u16, &str); 62] = [
+    (100, "CONTINUE"),
+    (101, "SWITCHING PROTOCOLS"),
+    (102, "PROCESSING"),
+    (103, "EARLY HINTS"),
+    (200, "OK"),
+    (201, "CREATED"),
+    (202, "ACCEPTED"),
+    (203, "NON-AUTHORITATIVE INFORMATION"),
+    (204, "NO CONTENT"),
+    (205, "RESET CONTENT"),
+    (206, "PARTIAL CONTENT"),
+    (207, "MULTI-STATUS"),
+    (208, "ALREADY REPORTED"),
+    (226, "IM USED"),
+    (300, "MULTIPLE CHOICES"),
+    (301, "MOVED PERMANENTLY"),
+    (302, "FOUND"),
+    (303, "SEE OTHER"),
+    (304, "NOT MODIFIED"),
+    (305, "USE PROXY"),
+    (307, "TEMPORARY REDIRECT"),
+    (308, "PERMANENT REDIRECT"),
+    (400, "BAD REQUEST"),
+    (401, "UNAUTHORIZED"),
+    (402, "PAYMENT REQUIRED"),
+    (403, "FORBIDDEN"),
+    (404, "NOT FOUND"),
+    (405, "METHOD NOT ALLOWED"),
+    (406, "NOT ACCEPTABLE"),
+    (407, "PROXY AUTHENTICATION REQUIRED"),
+    (408, "REQUEST TIMEOUT"),
+    (409, "CONFLICT"),
+    (410, "GONE"),
+    (411, "LENGTH REQUIRED"),
+    (412, "PRECONDITION FAILED"),
+    (413, "CONTENT TOO LARGE"),
+    (414, "URI TOO LONG"),
+    (415, "UNSUPPORTED MEDIA TYPE"),
+    (416, "RANGE NOT SATISFIABLE"),
+    (417, "EXPECTATION FAILED"),
+    (418, "I'M A TEAPOT"),
+    (421, "MISDIRECTED REQUEST"),
+    (422, "UNPROCESSABLE CONTENT"),
+    (423, "LOCKED"),
+    (424, "FAILED DEPENDENCY"),
+    (425, "TOO EARLY"),
+    (426, "UPGRADE REQUIRED"),
+    (428, "PRECONDITION REQUIRED"),
+    (429, "TOO MANY REQUESTS"),
+    (431, "REQUEST HEADER FIELDS TOO LARGE"),
+    (451, "UNAVAILABLE FOR LEGAL REASONS"),
+    (500, "INTERNAL SERVER ERROR"),
+    (501, "NOT IMPLEMENTED"),
+    (502, "BAD GATEWAY"),
+    (503, "SERVICE UNAVAILABLE"),
+    (504, "GATEWAY TIMEOUT"),
+    (505, "HTTP VERSION NOT SUPPORTED"),
+    (506, "VARIANT ALSO NEGOTIATES"),
+    (507, "INSUFFICIENT STORAGE"),
+    (508, "LOOP DETECTED"),
+    (510, "NOT EXTENDED"),
+    (511, "NETWORK AUTHENTICATION REQUIRED"),
+];
+
+/// 返回一个状态码对应的完整状态，例如 404 对应 `404 NOT FOUND`
+/// 未注册的状态码返回 None
+pub fn status(code: u16) -> Option<String> {
+    STATUS_CODES
+        .iter()
+        .find(|(a, _)| *a == code)
+        .map(|(a, reason)| format!("{} {}", a, reason))
+}
 
 /// 可以构造一个标准的 HTTP 响应字符串
 ///
 /// version: HTTP 相应的版本, 例如 `1.1`  
 /// state: HTTP 相应的状态, 例如 `400 BAD REQUEST`  
-/// header: 该哈希表的键表示相应头的键，值表示相应头的值  
+/// header: 所有的响应头，它们会按照被设置的顺序输出，参见 HttpHeaders  
 /// content: 可选的，相应主体部分，以 `Vec<u8>` 的方式储存
+/// content_stream: 可选的，以流的方式提供的相应主体，它会以分块传输编码的方式被逐块写出，而不必一次性读入内存
 ///
 /// 和 HttpRequest 不同, HttpResponse 用 `Vec<u8>` 的方式储存的原因是：  
 /// 1. 需要经常改变 content 的值以计算出最终的 content ，直接用 `Vec<u8>` 来储存可以避免转换和内存拷贝  
@@ -123,16 +992,18 @@ impl<'a, T> HttpRequest<'a, T> {
 pub struct HttpResponse {
     version: String,
     state: String,
-    headers: HashMap<String, String>,
+    headers: HttpHeaders,
     content: Option<Vec<u8>>,
+    content_stream: Option<Arc<Mutex<dyn Read + Send>>>,
 }
 impl HttpResponse {
     pub fn new() -> Self {
         HttpResponse {
             version: String::new(),
             state: String::new(),
-            headers: HashMap::new(),
+            headers: HttpHeaders::new(),
             content: None,
+            content_stream: None,
         }
     }
     pub fn set_version(&mut self, str: &str) {
@@ -141,12 +1012,57 @@ impl HttpResponse {
     pub fn set_state(&mut self, str: &str) {
         self.state = str.to_string()
     }
+    pub fn state(&self) -> &String {
+        &self.state
+    }
+    pub fn get_header(&self, k: &str) -> Option<&String> {
+        self.headers.get(k)
+    }
     pub fn set_header(&mut self, k: &str, v: String) -> Option<String> {
-        self.headers.insert(k.to_string(), v)
+        self.headers.insert(k, v)
+    }
+    /// 追加一个响应头，而不是替换同名的响应头，例如 `Set-Cookie`
+    #[cfg_attr(feature = "no-glisp", allow(dead_code))]
+    pub fn append_header(&mut self, k: &str, v: String) {
+        self.headers.append(k, v)
+    }
+    pub fn remove_header(&mut self, k: &str) -> Option<String> {
+        self.headers.remove(k)
     }
     pub fn set_content(&mut self, str: Vec<u8>) {
+        self.content_stream = None;
         self.content = Some(str)
     }
+    /// 设置一个流式的相应主体，它会替代 content
+    /// 如果能预先得知主体的长度，会设置 `Content-Length` ，否则会使用分块传输编码
+    pub fn set_content_stream(&mut self, stream: Arc<Mutex<dyn Read + Send>>, len: Option<u64>) {
+        self.content = None;
+        self.content_stream = Some(stream);
+        if let Some(len) = len {
+            self.headers.remove("Transfer-Encoding");
+            self.headers.insert("Content-Length", len.to_string());
+        } else {
+            self.headers.remove("Content-Length");
+            self.headers
+                .insert("Transfer-Encoding", "chunked".to_owned());
+        }
+    }
+    /// 丢弃相应主体，但保留 `Content-Length` 等描述主体的响应头
+    /// 用于 HEAD 请求，参见 RFC 9110 9.3.2
+    pub fn remove_content(&mut self) {
+        if !self.headers.contains_key("Content-Length")
+            && !self.headers.contains_key("Transfer-Encoding")
+        {
+            if let Some(a) = &self.content {
+                self.headers.insert("Content-Length", a.len().to_string());
+            }
+        }
+        self.content = None;
+        self.content_stream = None;
+    }
+    pub fn content_stream(&self) -> Option<&Arc<Mutex<dyn Read + Send>>> {
+        self.content_stream.as_ref()
+    }
     /// 根据不同需要，创建了 content_ref 和 content_unref 两个函数
     pub fn content_ref(&self) -> &Option<Vec<u8>> {
         &self.content
@@ -157,43 +1073,329 @@ impl HttpResponse {
     }
     /// 以 `Vec<u8>` 的形式返回响应流
     /// 不使用 string 的原因是可以直接兼容标准库相关函数
+    /// 流式的相应主体不会被包含在内，参见 write_to
     pub fn get_stream(&self) -> Vec<u8> {
+        let mut res = self.get_head_stream();
+        if let Some(a) = &self.content {
+            res.extend(a)
+        }
+        res
+    }
+    /// 以 `Vec<u8>` 的形式返回状态行和所有响应头，以空行结尾
+    ///
+    /// 如果没有设置 `Content-Length` 且不是流式的相应主体，会根据 content 自动补上（不能有主体的响应除外）
+    /// 否则在持久连接中，客户端无法得知该响应在何处结束
+    pub fn get_head_stream(&self) -> Vec<u8> {
         let mut res: Vec<u8> = format!("{} {}\r\n", self.version, self.state)
             .as_bytes()
             .to_vec();
-        for k in self.headers.keys() {
+        for (k, v) in self.headers.iter() {
+            res.extend(format!("{}: {}\r\n", k, v).as_bytes())
+        }
+        if !self.headers.contains_key("Content-Length")
+            && !self.headers.contains_key("Transfer-Encoding")
+            && self.content_stream.is_none()
+            && !self.is_bodiless_state()
+        {
             res.extend(
-                format!("{}: {}\r\n", k, self.headers.get(k).unwrap())
-                    .as_bytes()
-                    .to_vec(),
+                format!(
+                    "Content-Length: {}\r\n",
+                    self.content.as_ref().map_or(0, |a| a.len())
+                )
+                .as_bytes(),
             )
         }
         res.push(b'\r');
         res.push(b'\n');
-        if let Some(a) = &self.content {
-            res.extend(a)
-        }
         res
     }
+    /// 1xx, 204 和 304 响应永远没有主体，也不能有 `Content-Length` ，参见 RFC 9110 8.6
+    fn is_bodiless_state(&self) -> bool {
+        self.state.starts_with('1')
+            || self.state.starts_with("204")
+            || self.state.starts_with("304")
+    }
+    /// 将整个响应写入 w ，包括流式的相应主体
+    /// 如果设置了 `Transfer-Encoding: chunked` ，流式的主体会被逐块写出
+    /// 否则，它会被原样写出，这时只能通过关闭连接来表示主体的结束
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.get_stream())?;
+        if let Some(stream) = &self.content_stream {
+            let mut stream = match stream.lock() {
+                Ok(a) => a,
+                Err(_) => return Err(std::io::ErrorKind::Other.into()),
+            };
+            if self.headers.get("Transfer-Encoding").map(|a| a.as_str()) == Some("chunked") {
+                let mut chunked = ChunkedWriter::new(&mut *w);
+                std::io::copy(&mut *stream, &mut chunked)?;
+                chunked.finish()?;
+            } else if let Some(Ok(len)) = self.headers.get("Content-Length").map(|a| a.parse()) {
+                std::io::copy(&mut (&mut *stream).take(len), w)?;
+            } else {
+                std::io::copy(&mut *stream, w)?;
+            }
+        }
+        w.flush()
+    }
     /// 在初始化后，随时为相应追加默认的相应头
     /// TODO：设计名为 set_default_headers_unstable 的函数来更快的追加默认响应头
     pub fn set_default_headers(&mut self, server: &str) -> Result<(), SystemTimeError> {
         let time = super::time::Time::new();
-        self.headers.insert(
-            "Date".to_string(),
-            format!(
-                "{}, {} {} {} {:0>2}:{:0>2}:{:0>2} GMT",
-                time.wday_name()?,
-                time.day()?,
-                time.month_name()?,
-                time.year()?,
-                time.hour()?,
-                time.min()?,
-                time.sec()?
-            ),
-        );
-        self.headers
-            .insert("Server".to_string(), server.to_string());
+        self.headers.insert("Date", time.to_http_date()?);
+        self.headers.insert("Server", server.to_string());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn content_is_read_by_bytes() {
+        let mut stream = std::io::Cursor::new(b"line1\r\nline2\x00\xffNEXT".to_vec());
+        let mut request: HttpRequest<_> =
+            HttpRequest::from_string("POST / HTTP/1.1\r\nContent-Length: 14\r\n".to_owned())
+                .ok()
+                .unwrap();
+        request.set_content(Some(HttpContent::Length((&mut stream).take(14))));
+        assert_eq!(request.content_bytes().unwrap(), b"line1\r\nline2\x00\xff");
+        assert!(request.discard_content());
+        drop(request);
+        assert_eq!(stream.position(), 14);
+    }
+    #[test]
+    fn chunked_content() {
+        let mut stream = std::io::Cursor::new(
+            b"5;ext=1\r\nHello\r\n7\r\n, World\r\n0\r\nExpires: never\r\n\r\nNEXT".to_vec(),
+        );
+        let mut request: HttpRequest<_> =
+            HttpRequest::from_string("POST / HTTP/1.1\r\n".to_owned())
+                .ok()
+                .unwrap();
+        request.set_content(Some(HttpContent::Chunked(ChunkedReader::with_limit(
+            &mut stream,
+            u64::MAX,
+        ))));
+        assert_eq!(request.content_bytes().unwrap(), b"Hello, World");
+        match &request.content {
+            Some(HttpContent::Chunked(a)) => {
+                assert_eq!(a.trailers, vec![("Expires".to_owned(), "never".to_owned())])
+            }
+            _ => unreachable!(),
+        }
+        drop(request);
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "NEXT");
+
+        let mut stream = std::io::Cursor::new(b"5\r\nHello\r\n7\r\n, World\r\n0\r\n\r\n".to_vec());
+        let mut request: HttpRequest<_> =
+            HttpRequest::from_string("POST / HTTP/1.1\r\n".to_owned())
+                .ok()
+                .unwrap();
+        request.set_content(Some(HttpContent::Chunked(ChunkedReader::with_limit(
+            &mut stream,
+            10,
+        ))));
+        assert!(!request.discard_content());
+        assert!(request.content_too_large());
+
+        // 过长的块扩展和过多的尾部字段
+        let mut body = b"5;".to_vec();
+        body.extend(vec![b'a'; 100]);
+        body.extend(b"\r\nHello\r\n0\r\n\r\n");
+        let mut chunked = ChunkedReader::with_limit(std::io::Cursor::new(body), u64::MAX)
+            .limit_lines(64, 2, 1024);
+        assert!(chunked.read_to_end(&mut vec![]).is_err());
+        assert!(chunked.too_large);
+        let body = b"0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".to_vec();
+        let mut chunked = ChunkedReader::with_limit(std::io::Cursor::new(body), u64::MAX)
+            .limit_lines(64, 2, 1024);
+        assert!(chunked.read_to_end(&mut vec![]).is_err());
+        assert!(chunked.too_large);
+    }
+    #[test]
+    fn chunked_writer() {
+        let mut chunked = ChunkedWriter::new(vec![]);
+        chunked.write_all(b"Hello").unwrap();
+        chunked.write_all(b"").unwrap();
+        chunked.write_all(b", World").unwrap();
+        assert_eq!(
+            chunked.finish().unwrap(),
+            b"5\r\nHello\r\n7\r\n, World\r\n0\r\n\r\n"
+        );
+    }
+    #[test]
+    fn headers() {
+        let request: HttpRequest<()> = HttpRequest::from_string(
+            "GET / HTTP/1.1\r\ncontent-length:  32 \r\nAccept: a\r\naccept: b\r\n".to_owned(),
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(request.get_header("Content-Length").unwrap(), "32");
+        assert_eq!(
+            request.headers().get_all("ACCEPT").collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        let mut response = HttpResponse::new();
+        response.set_version("HTTP/1.1");
+        response.set_state("200 OK");
+        response.append_header("Set-Cookie", "a=1".to_owned());
+        response.set_header("Content-Type", "text/plain".to_owned());
+        response.append_header("Set-Cookie", "b=2".to_owned());
+        assert_eq!(
+            response.set_header("content-type", "text/html".to_owned()),
+            Some("text/plain".to_owned())
+        );
+        response.set_content(b"Hi".to_vec());
+        assert_eq!(
+            response.get_stream(),
+            b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nContent-Type: text/html\r\n\
+            Set-Cookie: b=2\r\nContent-Length: 2\r\n\r\nHi"
+        );
+        assert_eq!(response.remove_header("SET-COOKIE"), Some("a=1".to_owned()));
+        assert!(!response.headers.contains_key("Set-Cookie"));
+    }
+    #[test]
+    fn cookies() {
+        let request: HttpRequest<()> = HttpRequest::from_string(
+            "GET / HTTP/1.1\r\nCookie: a=1; b=\"x y\"\r\nCookie: bad; a=2\r\n".to_owned(),
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(
+            request.cookies(),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "x y".to_owned()),
+                ("a".to_owned(), "2".to_owned())
+            ]
+        );
+
+        let cookie = SetCookie::new("sid", "abc")
+            .attributes("path=/; Max-Age=60; HttpOnly; SameSite=None")
+            .unwrap()
+            .expires(784111777);
+        assert_eq!(
+            cookie.to_header().unwrap(),
+            "sid=abc; Path=/; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+            Secure; HttpOnly; SameSite=None"
+        );
+        assert!(SetCookie::new("sid", "a;b").to_header().is_none());
+        assert!(SetCookie::new("sid", "a").path("/;x").to_header().is_none());
+        assert!(SetCookie::new("sid", "a").attributes("Foo=1").is_none());
+    }
+    #[test]
+    fn strict() {
+        let check = |a: &str| check_strict(a).map_err(|e| e.state);
+        assert_eq!(check("GET /a?b HTTP/1.1\r\nHost: x\r\nA:\tb"), Ok(()));
+        assert_eq!(check("GET / HTTP/1.0"), Ok(()));
+        assert_eq!(check("OPTIONS * HTTP/1.0"), Ok(()));
+        let bad = "400 BAD REQUEST";
+        assert_eq!(check("GET  / HTTP/1.1\r\nHost: x"), Err(bad));
+        assert_eq!(check("G(T / HTTP/1.1\r\nHost: x"), Err(bad));
+        assert_eq!(check("GET a HTTP/1.1\r\nHost: x"), Err(bad));
+        assert_eq!(check("GET / HTTP/1.x\r\nHost: x"), Err(bad));
+        assert_eq!(
+            check("GET / HTTP/2.0\r\nHost: x"),
+            Err("505 HTTP VERSION NOT SUPPORTED")
+        );
+        assert_eq!(check("GET / HTTP/1.1"), Err(bad));
+        assert_eq!(check("GET / HTTP/1.1\r\nHost: x\r\nHost: y"), Err(bad));
+        assert_eq!(check("GET / HTTP/1.1\r\nHost : x"), Err(bad));
+        assert_eq!(check("GET / HTTP/1.1\r\nHost: x\r\nA: b\r\n c"), Err(bad));
+        assert_eq!(check("GET / HTTP/1.1\r\nHost: x\r\nA: b\rc"), Err(bad));
+
+        let framing = |a: &str| {
+            HttpRequest::<()>::from_string(a.to_owned())
+                .ok()
+                .unwrap()
+                .check_framing()
+                .map_err(|e| e.state)
+        };
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 5, 5"),
+            Ok(Some(5))
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 05"),
+            Ok(Some(5))
+        );
+        assert_eq!(framing("POST / HTTP/1.1"), Ok(None));
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: chunked"),
+            Ok(None)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 99999999999999999999"),
+            Err(bad)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6"),
+            Err(bad)
+        );
+        assert_eq!(framing("POST / HTTP/1.1\r\nContent-Length: +5"), Err(bad));
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked"),
+            Err(bad)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip"),
+            Err(bad)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked"),
+            Err("501 NOT IMPLEMENTED")
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.0\r\nTransfer-Encoding: chunked"),
+            Err(bad)
+        );
+    }
+    #[test]
+    fn encoding() {
+        let supported = ["gzip", "deflate"];
+        assert_eq!(
+            negotiate_encoding("gzip, deflate, br", &supported),
+            Some("gzip")
+        );
+        assert_eq!(
+            negotiate_encoding("deflate, gzip;q=0.5", &supported),
+            Some("deflate")
+        );
+        assert_eq!(
+            negotiate_encoding("*;q=0.1, gzip;q=0", &supported),
+            Some("deflate")
+        );
+        assert_eq!(negotiate_encoding("X-GZIP", &supported), Some("gzip"));
+        assert_eq!(negotiate_encoding("br, identity", &supported), None);
+        assert_eq!(negotiate_encoding("", &supported), None);
+    }
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("bytes=0-499", 1000), Ok(vec![(0, 499)]));
+        assert_eq!(
+            parse_range("bytes=500-, -100,0-0", 1000),
+            Ok(vec![(500, 999), (900, 999), (0, 0)])
+        );
+        assert_eq!(parse_range("bytes=900-2000", 1000), Ok(vec![(900, 999)]));
+        assert_eq!(parse_range("bytes=-2000", 1000), Ok(vec![(0, 999)]));
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            Err(RangeError::Unsatisfiable)
+        );
+        assert_eq!(parse_range("bytes=5-1", 1000), Err(RangeError::Invalid));
+        assert_eq!(parse_range("lines=1-2", 1000), Err(RangeError::Invalid));
+    }
+    #[test]
+    fn status_codes() {
+        assert_eq!(status(404).as_deref(), Some("404 NOT FOUND"));
+        assert_eq!(status(503).as_deref(), Some("503 SERVICE UNAVAILABLE"));
+        assert_eq!(status(499), None);
+        // 表中的状态码不能重复
+        for (i, (a, _)) in STATUS_CODES.iter().enumerate() {
+            assert!(STATUS_CODES[i + 1..].iter().all(|(b, _)| a != b));
+        }
+    }
+}
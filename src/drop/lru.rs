@@ -0,0 +1,118 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 一个以总大小为上限的 LRU (Least Recently Used) 缓存
+//! 每一项都有一个由调用者给出的大小，例如文件的字节数，总大小超出上限时，最久没有被使用的项会被淘汰
+
+use std::{borrow::Borrow, collections::BTreeMap};
+
+/// entries: 所有的项，值是 (值, 大小, 最后一次被使用的时刻)
+/// order: 按照最后一次被使用的时刻排列的键，最前面的是最久没有被使用的
+/// size: 所有项的大小之和
+/// tick: 一个递增的计数器，作为“时刻”使用
+pub struct LruCache<K, V> {
+    entries: BTreeMap<K, (V, usize, u64)>,
+    order: BTreeMap<u64, K>,
+    size: usize,
+    tick: u64,
+}
+impl<K: Ord + Clone, V> LruCache<K, V> {
+    /// 这是一个 const 函数，所以缓存可以被直接用作 static
+    pub const fn new() -> Self {
+        LruCache {
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+            size: 0,
+            tick: 0,
+        }
+    }
+    /// 返回键为 k 的值，它会成为最近被使用的项
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (v, _, tick) = self.entries.get_mut(k)?;
+        self.tick += 1;
+        let old = std::mem::replace(tick, self.tick);
+        if let Some(key) = self.order.remove(&old) {
+            self.order.insert(self.tick, key);
+        }
+        Some(v)
+    }
+    /// 插入一项，它会替换同一个键原有的值，然后淘汰最久没有被使用的项，直到总大小不超过 capacity
+    /// 如果这一项本身就超过了 capacity ，它不会被插入
+    pub fn insert(&mut self, k: K, v: V, size: usize, capacity: usize) {
+        self.remove(&k);
+        if size > capacity {
+            return;
+        }
+        while self.size + size > capacity {
+            match self.order.pop_first() {
+                Some((_, a)) => {
+                    if let Some((_, size, _)) = self.entries.remove(&a) {
+                        self.size -= size;
+                    }
+                }
+                None => break,
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, k.clone());
+        self.entries.insert(k, (v, size, self.tick));
+        self.size += size;
+    }
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (v, size, tick) = self.entries.remove(k)?;
+        self.order.remove(&tick);
+        self.size -= size;
+        Some(v)
+    }
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+    /// 所有项的大小之和
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn eviction() {
+        let mut cache = LruCache::new();
+        cache.insert("a".to_owned(), 1, 4, 10);
+        cache.insert("b".to_owned(), 2, 4, 10);
+        // a 被使用过，所以 b 是最久没有被使用的
+        assert_eq!(cache.get("a"), Some(&1));
+        cache.insert("c".to_owned(), 3, 4, 10);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.get("c"), Some(&3));
+        assert_eq!(cache.size(), 8);
+
+        cache.insert("a".to_owned(), 4, 2, 10);
+        assert_eq!(cache.get("a"), Some(&4));
+        assert_eq!(cache.size(), 6);
+        cache.insert("d".to_owned(), 5, 11, 10);
+        assert_eq!(cache.get("d"), None);
+        assert_eq!(cache.size(), 6);
+        assert_eq!(cache.remove("c"), Some(3));
+        assert_eq!(cache.size(), 2);
+    }
+}
@@ -21,12 +21,57 @@
 //!
 //! ## mempool
 //! 一个内存池库，用以在高并发下避免过多的重新内存分配
+//!
+//! ## url
+//! 解析请求目标中的路径和查询字符串，包括百分号解码
+//!
+//! ## deflate
+//! 不依赖任何外部库的 DEFLATE 压缩，以及 gzip 和 zlib 格式
+//!
+//! ## form
+//! 解析表单主体，包括 urlencoded 和 multipart/form-data ，上传的文件会被流式的写入临时文件
+//!
+//! ## websocket
+//! WebSocket 的握手，以及帧的读取和写出
+//!
+//! ## sse
+//! Server-Sent Events 的消息格式
+//!
+//! ## sha1
+//! SHA-1 哈希算法，只用于 WebSocket 握手
+//!
+//! ## lru
+//! 以总大小为上限的 LRU 缓存，被用于缓存文件的内容
+//!
+//! ## signal
+//! 监听 Unix 信号，例如用于重新加载配置的 SIGHUP
+//!
+//! ## socket
+//! 创建监听套接字，可以设置 backlog ，IPv6 的地址不会占用同一端口的 IPv4 地址
+//!
+//! ## epoll
+//! Linux 的 epoll ，被用于 event-mode 的事件循环
 
 pub mod base64;
+pub mod deflate;
+#[cfg(target_os = "linux")]
+pub mod epoll;
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub mod form;
 pub mod http;
 pub mod log;
+pub mod lru;
 pub mod mempool;
 pub mod random;
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub mod sha1;
+pub mod signal;
+pub mod socket;
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub mod sse;
 pub mod thread;
 pub mod time;
 pub mod tool;
+pub mod url;
+#[cfg_attr(feature = "no-glisp", allow(dead_code))]
+pub mod websocket;
@@ -0,0 +1,142 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SHA-1 哈希算法，参见[此文档](https://www.rfc-editor.org/rfc/rfc3174)
+//! SHA-1 已经不再安全，它只应该被用于 WebSocket 握手这类协议规定必须使用它的地方
+//! 用法与 https::sha256 相同
+
+const H: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+pub struct Sha1 {
+    state: [u32; 5],
+    completed_data_blocks: u64,
+    pending: [u8; 64],
+    num_pending: usize,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self {
+            state: H,
+            completed_data_blocks: 0,
+            pending: [0u8; 64],
+            num_pending: 0,
+        }
+    }
+}
+
+impl Sha1 {
+    fn update_state(state: &mut [u32; 5], data: &[u8]) {
+        let mut w = [0u32; 80];
+        for (w, d) in w.iter_mut().zip(data.chunks_exact(4)) {
+            *w = u32::from_be_bytes([d[0], d[1], d[2], d[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = *state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (v, h) in state.iter_mut().zip([a, b, c, d, e]) {
+            *v = v.wrapping_add(h);
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.num_pending > 0 {
+            let n = data.len().min(64 - self.num_pending);
+            self.pending[self.num_pending..self.num_pending + n].copy_from_slice(&data[..n]);
+            self.num_pending += n;
+            data = &data[n..];
+            if self.num_pending < 64 {
+                return;
+            }
+            Self::update_state(&mut self.state, &self.pending);
+            self.completed_data_blocks += 1;
+            self.num_pending = 0;
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            Self::update_state(&mut self.state, block);
+            self.completed_data_blocks += 1;
+        }
+        let remain = blocks.remainder();
+        self.pending[..remain.len()].copy_from_slice(remain);
+        self.num_pending = remain.len();
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let data_bits = self.completed_data_blocks * 512 + self.num_pending as u64 * 8;
+        let mut pending = [0u8; 72];
+        pending[0] = 128;
+
+        let offset = if self.num_pending < 56 {
+            56 - self.num_pending
+        } else {
+            120 - self.num_pending
+        };
+
+        pending[offset..offset + 8].copy_from_slice(&data_bits.to_be_bytes());
+        self.update(&pending[..offset + 8]);
+
+        let mut res = [0u8; 20];
+        for (a, h) in res.chunks_exact_mut(4).zip(self.state) {
+            a.copy_from_slice(&h.to_be_bytes());
+        }
+        res
+    }
+
+    #[allow(dead_code)]
+    pub fn digest(data: &[u8]) -> [u8; 20] {
+        let mut sha1 = Self::default();
+        sha1.update(data);
+        sha1.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn digest() {
+        let hex = |a: [u8; 20]| a.iter().map(|a| format!("{:02x}", a)).collect::<String>();
+        assert_eq!(
+            hex(Sha1::digest(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        let mut sha1 = Sha1::default();
+        for _ in 0..1000 {
+            sha1.update(&[b'a'; 1000][..999]);
+            sha1.update(b"a");
+        }
+        assert_eq!(
+            hex(sha1.finish()),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+}
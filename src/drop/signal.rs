@@ -0,0 +1,67 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 不依赖任何外部库的 Unix 信号处理
+//! 信号处理函数中几乎不能做任何事情，所以它只记录“收到了某个信号”，由其它线程通过 take 取走并处理
+//! 在非 Unix 平台上，listen 什么也不做，take 总是返回 false
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGTERM: i32 = 15;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub const SIGUSR1: i32 = 30;
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+pub const SIGUSR1: i32 = 10;
+
+/// 每一个信号是否已经收到但还没有被取走
+static RECEIVED: [AtomicBool; 32] = [const { AtomicBool::new(false) }; 32];
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+extern "C" fn handler(signum: i32) {
+    if let Some(a) = RECEIVED.get(signum as usize) {
+        a.store(true, Ordering::SeqCst);
+    }
+}
+
+/// 开始监听一个信号，此后收到它时，进程不会再执行默认的行为（例如 SIGHUP 默认会结束进程）
+pub fn listen(signum: i32) {
+    #[cfg(unix)]
+    unsafe {
+        signal(signum, handler);
+    }
+    #[cfg(not(unix))]
+    let _ = signum;
+}
+
+/// 返回自上一次调用以来是否收到过这个信号
+pub fn take(signum: i32) -> bool {
+    RECEIVED
+        .get(signum as usize)
+        .is_some_and(|a| a.swap(false, Ordering::SeqCst))
+}
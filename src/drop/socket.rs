@@ -0,0 +1,139 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 不依赖任何外部库的监听套接字的创建
+//! 标准库的 TcpListener::bind 无法设置 backlog ，也无法在绑定之前设置 IPV6_V6ONLY
+//! 而在 Linux 上，没有设置 IPV6_V6ONLY 的 `[::]:80` 也会占用 `0.0.0.0:80` ，所以两者无法同时被监听
+//! 在 Linux 之外的平台上，这里退回到标准库，backlog 会被忽略
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+};
+
+/// 标准库在 Linux 上使用的 backlog
+pub const DEFAULT_BACKLOG: u32 = 128;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    pub const AF_INET: i32 = 2;
+    pub const AF_INET6: i32 = 10;
+    pub const SOCK_STREAM: i32 = 1;
+    pub const SOCK_CLOEXEC: i32 = 0o2000000;
+    pub const SOL_SOCKET: i32 = 1;
+    pub const SO_REUSEADDR: i32 = 2;
+    pub const IPPROTO_IPV6: i32 = 41;
+    pub const IPV6_V6ONLY: i32 = 26;
+
+    #[repr(C)]
+    pub struct SockaddrIn {
+        pub family: u16,
+        pub port: [u8; 2],
+        pub addr: [u8; 4],
+        pub zero: [u8; 8],
+    }
+    #[repr(C)]
+    pub struct SockaddrIn6 {
+        pub family: u16,
+        pub port: [u8; 2],
+        pub flowinfo: u32,
+        pub addr: [u8; 16],
+        pub scope_id: u32,
+    }
+
+    extern "C" {
+        pub fn socket(domain: i32, ty: i32, protocol: i32) -> i32;
+        pub fn setsockopt(fd: i32, level: i32, name: i32, value: *const i32, len: u32) -> i32;
+        pub fn bind(fd: i32, addr: *const u8, len: u32) -> i32;
+        pub fn listen(fd: i32, backlog: i32) -> i32;
+    }
+}
+
+/// 创建一个监听 addr 的 TcpListener
+/// IPv6 的地址只接受 IPv6 的连接，与监听同一端口的 IPv4 地址互不影响
+#[cfg(target_os = "linux")]
+pub fn bind(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use sys::*;
+
+    let cvt = |a: i32| {
+        if a < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(a)
+        }
+    };
+    let domain = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+    let fd = cvt(unsafe { socket(domain, SOCK_STREAM | SOCK_CLOEXEC, 0) })?;
+    // 出错时，fd 会在 OwnedFd 被丢弃时被关闭
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw = std::os::fd::AsRawFd::as_raw_fd(&fd);
+    let one: i32 = 1;
+    cvt(unsafe { setsockopt(raw, SOL_SOCKET, SO_REUSEADDR, &one, 4) })?;
+    match addr {
+        SocketAddr::V4(a) => {
+            let sockaddr = SockaddrIn {
+                family: AF_INET as u16,
+                port: a.port().to_be_bytes(),
+                addr: a.ip().octets(),
+                zero: [0; 8],
+            };
+            cvt(unsafe {
+                bind(
+                    raw,
+                    &sockaddr as *const SockaddrIn as *const u8,
+                    std::mem::size_of::<SockaddrIn>() as u32,
+                )
+            })?;
+        }
+        SocketAddr::V6(a) => {
+            cvt(unsafe { setsockopt(raw, IPPROTO_IPV6, IPV6_V6ONLY, &one, 4) })?;
+            let sockaddr = SockaddrIn6 {
+                family: AF_INET6 as u16,
+                port: a.port().to_be_bytes(),
+                flowinfo: a.flowinfo(),
+                addr: a.ip().octets(),
+                scope_id: a.scope_id(),
+            };
+            cvt(unsafe {
+                bind(
+                    raw,
+                    &sockaddr as *const SockaddrIn6 as *const u8,
+                    std::mem::size_of::<SockaddrIn6>() as u32,
+                )
+            })?;
+        }
+    }
+    cvt(unsafe { listen(raw, backlog.min(i32::MAX as u32) as i32) })?;
+    Ok(TcpListener::from(fd))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind(addr: SocketAddr, _backlog: u32) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn dual_stack() {
+        let v4 = bind("127.0.0.1:0".parse().unwrap(), 16).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        // 同一端口的 IPv6 地址可以被同时监听（如果这台机器支持 IPv6 ）
+        if let Ok(v6) = bind(SocketAddr::new("::1".parse().unwrap(), port), 16) {
+            assert_eq!(v6.local_addr().unwrap().port(), port);
+            std::net::TcpStream::connect(("::1", port)).unwrap();
+            assert!(v6.accept().is_ok());
+        }
+        std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(v4.accept().is_ok());
+        assert!(bind("127.0.0.1:0".parse().unwrap(), 16).is_ok());
+        assert!(bind(v4.local_addr().unwrap(), 16).is_err());
+    }
+}
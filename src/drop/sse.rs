@@ -0,0 +1,46 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Server-Sent Events 的消息格式
+//! 参见[此文档](https://html.spec.whatwg.org/multipage/server-sent-events.html)
+
+/// 构造一个事件
+/// data 中的每一行都会成为一个 `data:` 字段，浏览器会把它们重新用换行连接起来
+/// event 和 id 中不能有换行，id 中也不能有 NUL ，这些字符会被去掉
+pub fn format_event(data: &str, event: Option<&str>, id: Option<&str>) -> String {
+    let mut res = String::new();
+    if let Some(a) = event {
+        res += &format!("event: {}\n", a.replace(['\r', '\n'], ""));
+    }
+    if let Some(a) = id {
+        res += &format!("id: {}\n", a.replace(['\r', '\n', '\0'], ""));
+    }
+    for line in data.replace("\r\n", "\n").split(['\r', '\n']) {
+        res += &format!("data: {}\n", line);
+    }
+    res + "\n"
+}
+
+/// 构造一个注释，浏览器会忽略它，通常被用作心跳以免连接被中间的代理关闭
+pub fn format_comment(str: &str) -> String {
+    format!(": {}\n\n", str.replace(['\r', '\n'], " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn event() {
+        assert_eq!(
+            format_event("a\r\nb\nc", Some("up\ndate"), Some("7")),
+            "event: update\nid: 7\ndata: a\ndata: b\ndata: c\n\n"
+        );
+        assert_eq!(format_event("", None, None), "data: \n\n");
+        assert_eq!(format_comment("ping"), ": ping\n\n");
+    }
+}
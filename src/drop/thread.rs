@@ -5,30 +5,172 @@
  * along with this program;
  * if not, see <https://www.gnu.org/licenses/>.
  */
-use std::thread::{spawn, JoinHandle};
+use std::{
+    collections::VecDeque,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::spawn,
+    time::{Duration, Instant},
+};
 
-/// 创建一个线程池，你永远不用在乎结构体内是什么
+type Job = Box<dyn FnOnce() + Send>;
+
+/// 一个固定大小的线程池，其中的线程会一直存在，并从一个有上限的队列中取出任务执行
+/// 它可以被廉价的 clone ，所有的 clone 共用同一组线程和同一个队列
+#[derive(Clone)]
 pub struct ThreadPool {
-    joinhandles: Vec<JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+struct Shared {
+    queue: Mutex<VecDeque<Job>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    queue_depth: usize,
+    workers: Vec<Worker>,
 }
+#[derive(Default)]
+struct Worker {
+    busy: AtomicBool,
+    handled: AtomicU64,
+    busy_time: AtomicU64, // 以毫秒为单位
+}
+
+/// 一个线程在某一时刻的统计信息
+/// busy: 是否正在执行任务
+/// handled: 已经执行完的任务的数量
+/// busy_time: 执行任务所用的总时间
+pub struct WorkerStats {
+    pub busy: bool,
+    pub handled: u64,
+    pub busy_time: Duration,
+}
+
 impl ThreadPool {
-    pub fn new() -> Self {
-        ThreadPool {
-            joinhandles: Vec::new(),
-        }
-    }
-    /// stop_if_num_gt: 如果线程池内的线程数超过给定值，则等待
-    /// 直到线程池内的线程数小于给定值，然后继续运行本线程
-    /// func: 函数或闭包，通常来讲必须是全局的或在 main 函数中被定义
-    /// 除非使用了发散函数的技巧
-    pub fn add(
-        &mut self,
-        stop_if_num_gt: usize,
-        func: impl FnOnce() + std::marker::Send + 'static,
-    ) {
-        if self.joinhandles.len() == stop_if_num_gt + 1 {
-            self.joinhandles.remove(0).join().unwrap();
-        }
-        self.joinhandles.push(spawn(func));
+    /// size: 线程的数量，至少为 1
+    /// queue_depth: 队列中最多可以有多少个等待执行的任务，至少为 1
+    pub fn new(size: usize, queue_depth: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            queue_depth: queue_depth.max(1),
+            workers: (0..size.max(1)).map(|_| Worker::default()).collect(),
+        });
+        for i in 0..shared.workers.len() {
+            let shared = shared.clone();
+            spawn(move || shared.work(i));
+        }
+        ThreadPool { shared }
+    }
+    /// 将一个任务放入队列，如果队列已满，则等待，直到队列中有空位
+    pub fn add(&self, func: impl FnOnce() + Send + 'static) {
+        let mut queue = self.shared.lock_queue();
+        while queue.len() >= self.shared.queue_depth {
+            queue = self
+                .shared
+                .not_full
+                .wait(queue)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        queue.push_back(Box::new(func));
+        self.shared.not_empty.notify_one();
+    }
+    /// 队列是否已满，此时 add 会等待
+    pub fn is_full(&self) -> bool {
+        self.queued() >= self.shared.queue_depth
+    }
+    /// 队列中等待执行的任务的数量
+    pub fn queued(&self) -> usize {
+        self.shared.lock_queue().len()
+    }
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.shared
+            .workers
+            .iter()
+            .map(|a| WorkerStats {
+                busy: a.busy.load(Ordering::Relaxed),
+                handled: a.handled.load(Ordering::Relaxed),
+                busy_time: Duration::from_millis(a.busy_time.load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+}
+impl Shared {
+    /// 任务中的 panic 不会让锁中毒，所以这里忽略中毒
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, VecDeque<Job>> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// 第 i 个线程的主循环
+    /// 任务中的 panic 会被捕获，这样一个出错的任务不会让线程池少一个线程
+    fn work(&self, i: usize) {
+        let worker = &self.workers[i];
+        loop {
+            let job = {
+                let mut queue = self.lock_queue();
+                loop {
+                    if let Some(a) = queue.pop_front() {
+                        break a;
+                    }
+                    queue = self
+                        .not_empty
+                        .wait(queue)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            self.not_full.notify_one();
+
+            worker.busy.store(true, Ordering::Relaxed);
+            let start = Instant::now();
+            let _ = catch_unwind(AssertUnwindSafe(job));
+            worker
+                .busy_time
+                .fetch_add(start.elapsed().as_millis() as u64, Ordering::Relaxed);
+            worker.handled.fetch_add(1, Ordering::Relaxed);
+            worker.busy.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn pool() {
+        let pool = ThreadPool::new(2, 4);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.add(|| panic!("任务中的 panic 不应该影响线程池"));
+        for i in 0..8 {
+            let sender = sender.clone();
+            pool.add(move || sender.send(i).unwrap());
+        }
+        let mut received: Vec<i32> = (0..8).map(|_| receiver.recv().unwrap()).collect();
+        received.sort();
+        assert_eq!(received, (0..8).collect::<Vec<_>>());
+
+        // 线程都在等待时，队列中的任务不会被取出
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..2 {
+            let receiver = receiver.clone();
+            pool.add(move || {
+                let _ = receiver.lock().unwrap().recv();
+            });
+        }
+        while pool.stats().iter().filter(|a| a.busy).count() != 2 {
+            std::thread::yield_now();
+        }
+        // 第二个任务在等待第一个任务释放锁，所以两个线程都在忙
+        for _ in 0..4 {
+            pool.add(|| ());
+        }
+        assert!(pool.is_full());
+        drop(sender);
+        while pool.queued() != 0 {
+            std::thread::yield_now();
+        }
+        assert!(!pool.is_full());
     }
 }
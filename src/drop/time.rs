@@ -20,95 +20,54 @@ pub struct Time {
     hour: u32,
     min: u32,
     sec: u32,
+    #[allow(dead_code)]
     yday: u32,
+    #[allow(dead_code)]
     mday: u32,
     wday: u32,
 }
 
 impl Time {
-    /// 由我和 min0911 为 Plant-OS 项目编写的 C 代码改写而来，原版本有注释
-    /// 参见[此文档](https://github.com/min0911Y/Plant-OS/blob/main/apps/libp/time.c)
-    /// 2024 年 2 月为止，该实现有 bug ，所以请查看[最原始版本](
-    /// https://github.com/ZhouZhihaos/Powerint-DOS-386/blob/31222fad9ae303daa35d27844cc335c87ee2f1c7/apps/src/time.c)
+    /// 将时间戳 (UTC) 转换为日历时间
+    /// 算法参见[此文档](https://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+    ///
+    /// 此前由 Plant-OS 的 C 代码改写而来的版本在闰年、年中日和星期的计算上均有错误，故被替换
     fn builder(timestamp: u64) -> Self {
-        let mut y = 1970;
-        let mut _timestamp = timestamp;
-        loop {
-            if (y % 4 == 0 && y % 100 != 0) || y & 400 == 0 {
-                _timestamp -= 31622400;
-                if _timestamp <= 31536000 {
-                    y += 1;
-                    break;
-                }
-            } else {
-                _timestamp -= 31536000;
-                if _timestamp <= 31536000
-                    || ((((y + 1) % 4 == 0 && (y + 1) % 100 != 0) || (y + 1) % 400 == 0)
-                        && timestamp <= 31622400)
-                {
-                    y += 1;
-                    break;
-                }
-            }
-            y += 1;
-        }
-        let mut _month = 1;
-        let table1 = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-        let table2 = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
-        if ((y + 1) % 4 == 0 && (y + 1) % 100 != 0) || (y + 1) % 400 == 0 {
-            while _timestamp > table2[_month - 1] * 86400 {
-                _timestamp -= table2[_month - 1] * 86400;
-                _month += 1;
-            }
-        } else {
-            while _timestamp > table2[_month - 1] * 86400 {
-                _timestamp -= table1[_month - 1] * 86400;
-                _month += 1;
-            }
-        }
+        let days = timestamp / 86400;
+        let secs = timestamp % 86400;
 
-        let mut tmp_time = Time {
+        // 以 0000-03-01 为纪元，这样闰日恰好是每年的最后一天
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        let table = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let yday = table[month as usize - 1] + day + if leap && month > 2 { 1 } else { 0 };
+
+        Time {
             timestamp: Ok(timestamp),
-            year: y,
-            month: _month as u32,
-            day: (_timestamp / 86400 + 1) as u32,
-            hour: (_timestamp % 86400 / 3600) as u32,
-            min: (_timestamp % 86400 % 3600 / 60) as u32,
-            sec: (_timestamp % 86400 % 3600 % 60) as u32,
-            yday: 0,
-            mday: 0,
-            wday: 0,
-        };
-        let mut days = 0;
-        if (tmp_time.year % 4 == 0 && tmp_time.year % 100 != 0) || tmp_time.year % 400 == 0 {
-            let mut i = 0;
-            while i < _month - 1 {
-                days += table2[i];
-                i += 1;
-            }
-        } else {
-            let mut i = 0;
-            while i < _month - 1 {
-                days += table1[i];
-                i += 1;
-            }
-        }
-        tmp_time.yday = days as u32 + tmp_time.day;
-        tmp_time.mday = tmp_time.day;
-        let mut total_days = tmp_time.yday - 1;
-        let start_year = 1900;
-        let mut year1 = start_year;
-        while year1 < tmp_time.year {
-            if (year1 % 4 == 0 && year1 % 100 != 0) || year1 % 400 == 0 {
-                total_days += 356;
-            } else {
-                total_days += 365;
-            }
-            year1 += 1;
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: (secs / 3600) as u32,
+            min: (secs % 3600 / 60) as u32,
+            sec: (secs % 60) as u32,
+            yday: yday as u32,
+            mday: day as u32,
+            // 1970-01-01 是星期四，1 代表星期一，7 代表星期日
+            wday: ((days + 3) % 7 + 1) as u32,
         }
-        let weekday = total_days % 7;
-        tmp_time.wday = weekday;
-        tmp_time
+    }
+    /// 从以秒为单位的时间戳 (UTC) 构造
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        Time::builder(timestamp)
     }
     pub fn new() -> Self {
         match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
@@ -210,6 +169,67 @@ impl Time {
             Err(error) => Err(error.clone()),
         }
     }
+    /// 格式化为 HTTP 日期 (IMF-fixdate)，例如 `Sun, 06 Nov 1994 08:49:37 GMT`
+    /// 参见[此文档](https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7)
+    pub fn to_http_date(&self) -> Result<String, SystemTimeError> {
+        Ok(format!(
+            "{}, {:0>2} {} {} {:0>2}:{:0>2}:{:0>2} GMT",
+            self.wday_name()?,
+            self.day()?,
+            self.month_name()?,
+            self.year()?,
+            self.hour()?,
+            self.min()?,
+            self.sec()?
+        ))
+    }
+}
+
+/// 将 HTTP 日期解析为以秒为单位的时间戳
+/// 除了 IMF-fixdate 之外，也接受过时的 RFC 850 格式和 asctime 格式，参见 RFC 9110 5.6.7
+/// 星期几会被忽略，无法解析时返回 None
+pub fn parse_http_date(str: &str) -> Option<u64> {
+    let months = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = str.split_whitespace().collect();
+    let (day, month, year, time) = match parts[..] {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [_, day, month, year, time, "GMT"] => (day, month, year.parse::<u64>().ok()?, time),
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year = year.parse::<u64>().ok()?;
+            // 两位数的年份如果看起来超过未来 50 年，则被认为是上个世纪的，这里简单的以 70 为界
+            let year = match year {
+                0..=69 => year + 2000,
+                70..=99 => year + 1900,
+                _ => year,
+            };
+            (day, month, year, time)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [_, month, day, time, year] => (day, month, year.parse::<u64>().ok()?, time),
+        _ => return None,
+    };
+    let month = months.iter().position(|a| *a == month)? as u64 + 1;
+    let day = day.parse::<u64>().ok()?;
+    let mut time = time.split(':').map(|a| a.parse::<u64>().ok());
+    let (hour, min, sec) = (time.next()??, time.next()??, time.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    // builder 的逆运算
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + min * 60 + sec)
 }
 
 pub fn get_formatted_time(use_localtime: bool) -> Result<String, SystemTimeError> {
@@ -254,3 +274,34 @@ pub mod time_difference {
         unsafe { time(std::ptr::null()) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn http_date() {
+        let time = Time::from_timestamp(784111777);
+        assert_eq!(
+            time.to_http_date().unwrap(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(
+            Time::from_timestamp(1709210096).to_http_date().unwrap(),
+            "Thu, 29 Feb 2024 12:34:56 GMT"
+        );
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784111777)
+        );
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(784111777)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 12:34:56 GMT"),
+            Some(1709210096)
+        );
+        assert_eq!(parse_http_date("06 Nov 1994"), None);
+    }
+}
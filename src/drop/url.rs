@@ -0,0 +1,152 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 解析请求目标 (request-target) 中的路径和查询字符串
+//! 参见[此文档](https://www.rfc-editor.org/rfc/rfc3986)
+
+/// 百分号解码，例如 `my%20file` 会被解码为 `my file`
+/// plus_as_space: 是否将 `+` 解码为空格，这只适用于查询字符串和表单
+///
+/// 如果出现了不完整的百分号编码，或者解码的结果不是合法的 UTF-8 ，则返回 None
+pub fn percent_decode(str: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = str.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                if !hex.bytes().all(|a| a.is_ascii_hexdigit()) {
+                    return None;
+                }
+                res.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+                continue;
+            }
+            b'+' if plus_as_space => res.push(b' '),
+            a => res.push(a),
+        }
+        i += 1;
+    }
+    String::from_utf8(res).ok()
+}
+
+/// 百分号编码一个路径段，例如 `my file` 会被编码为 `my%20file`
+/// 只有非保留字符 (unreserved) 不会被编码，所以 `/` 也会被编码，参见 RFC 3986 2.3
+pub fn percent_encode(str: &str) -> String {
+    let mut res = String::with_capacity(str.len());
+    for a in str.bytes() {
+        if a.is_ascii_alphanumeric() || b"-._~".contains(&a) {
+            res.push(a as char);
+        } else {
+            res += &format!("%{:02X}", a);
+        }
+    }
+    res
+}
+
+/// 解析 `a=1&b=2` 形式的查询字符串，保留原有的顺序和重复的键
+/// 没有 `=` 的项的值是空字符串，无法解码的项会被忽略
+pub fn parse_query(str: &str) -> Vec<(String, String)> {
+    str.split('&')
+        .filter(|a| !a.is_empty())
+        .filter_map(|a| {
+            let (k, v) = a.split_once('=').unwrap_or((a, ""));
+            Some((percent_decode(k, true)?, percent_decode(v, true)?))
+        })
+        .collect()
+}
+
+/// 将路径按 `/` 分割为段，并分别解码每一段
+/// 空的段和 `.` 会被去掉，`..` 会去掉上一段，但永远不会越过根目录
+///
+/// 先分割再解码，所以被编码的 `%2F` 不会被当作分隔符
+pub fn path_segments(path: &str) -> Option<Vec<String>> {
+    let mut segments: Vec<String> = vec![];
+    for segment in path.split('/') {
+        match percent_decode(segment, false)?.as_str() {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            a => segments.push(a.to_owned()),
+        }
+    }
+    Some(segments)
+}
+
+/// 将请求目标解析为 (规范化的路径, 路径段, 查询字符串)
+/// 规范化的路径以 `/` 开头，如果原路径以 `/` 结尾，它也会保留结尾的 `/`
+/// 路径段中被解码的 `%` 、 `/` 、 `\` 和 NUL 在规范化的路径中仍然保持编码，所以 `/a%2Fb` 与 `/a/b` 和 `/a%252Fb` 都不同
+///
+/// 例如，`/a/./b%20c/../d/?x=1` 会被解析为 `("/a/d/", ["a", "d"], "x=1")`
+pub fn parse_target(target: &str) -> Option<(String, Vec<String>, &str)> {
+    let target = target.split_once('#').map_or(target, |(a, _)| a);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    // absolute-form，例如 `http://example.com/index.html` ，参见 RFC 9112 3.2.2
+    let path = match path.split_once("://") {
+        Some((_, a)) => a.find('/').map_or("/", |i| &a[i..]),
+        None => path,
+    };
+    let segments = path_segments(path)?;
+    let mut normalized = "/".to_owned()
+        + &segments
+            .iter()
+            .map(|a| {
+                a.replace('%', "%25")
+                    .replace('/', "%2F")
+                    .replace('\\', "%5C")
+                    .replace('\0', "%00")
+            })
+            .collect::<Vec<String>>()
+            .join("/");
+    if !segments.is_empty() && path.ends_with('/') {
+        normalized.push('/');
+    }
+    Some((normalized, segments, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn target() {
+        let (path, segments, query) =
+            parse_target("/a/./b%20c/../d%2Fe/?x=1&y=%E4%BD%A0+a").unwrap();
+        assert_eq!(path, "/a/d%2Fe/");
+        assert_eq!(segments, vec!["a", "d/e"]);
+        assert_eq!(
+            parse_query(query),
+            vec![
+                ("x".to_owned(), "1".to_owned()),
+                ("y".to_owned(), "你 a".to_owned())
+            ]
+        );
+        assert_eq!(parse_target("/../../index.html").unwrap().0, "/index.html");
+        assert_ne!(
+            parse_target("/a%2Fb").unwrap().0,
+            parse_target("/a/b").unwrap().0
+        );
+        assert_ne!(
+            parse_target("/a%2Fb").unwrap().0,
+            parse_target("/a%252Fb").unwrap().0
+        );
+        assert_eq!(parse_target("/a%252Fb").unwrap().0, "/a%252Fb");
+        assert_eq!(parse_target("/100%25").unwrap().0, "/100%25");
+        let (path, segments, _) = parse_target("/..%2F../x%5Cy%00").unwrap();
+        assert_eq!(path, "/..%2F../x%5Cy%00");
+        assert_eq!(segments, vec!["../..", "x\\y\0"]);
+        assert_eq!(parse_target("http://a.com").unwrap().0, "/");
+        assert!(parse_target("/%zz").is_none());
+        assert_eq!(percent_encode("你 a/b.txt"), "%E4%BD%A0%20a%2Fb.txt");
+        assert_eq!(
+            percent_decode(&percent_encode("d/e f"), false).unwrap(),
+            "d/e f"
+        );
+    }
+}
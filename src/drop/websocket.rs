@@ -0,0 +1,259 @@
+/* Tiny-Tiny-Web/Drop
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License Version 3
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! WebSocket 的握手与帧，参见[此文档](https://www.rfc-editor.org/rfc/rfc6455)
+//! 这里只实现了服务器一侧：客户端发来的帧必须被掩码，服务器发出的帧不被掩码
+
+use std::io::{Read, Write};
+
+use super::http::HttpRequest;
+
+/// 握手时用于计算 `Sec-WebSocket-Accept` 的固定 GUID，参见 RFC 6455 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+impl Opcode {
+    fn from_u8(a: u8) -> Option<Self> {
+        Some(match a {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            _ => return None,
+        })
+    }
+    fn to_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+    /// 控制帧不能被分片，并且负载不能超过 125 字节
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// 一个已经去掉掩码的帧
+#[derive(Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+/// 读取帧时的错误，每一种都对应一个关闭连接时使用的状态码，参见 RFC 6455 7.4.1
+/// Io: 连接中断或超时，这时已经无法发送关闭帧
+/// Protocol: 违反协议的帧，对应 1002
+/// TooLarge: 负载超过了限制，对应 1009
+#[derive(Debug)]
+pub enum FrameError {
+    Io,
+    Protocol,
+    TooLarge,
+}
+impl FrameError {
+    pub fn close_code(&self) -> Option<u16> {
+        match self {
+            FrameError::Io => None,
+            FrameError::Protocol => Some(1002),
+            FrameError::TooLarge => Some(1009),
+        }
+    }
+}
+
+/// 计算握手响应中的 `Sec-WebSocket-Accept`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = super::sha1::Sha1::default();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    super::base64::encode(&sha1.finish())
+}
+
+/// 检查一个请求是否是合法的 WebSocket 握手，如果是，返回 `Sec-WebSocket-Accept`
+/// 如果请求根本不是 WebSocket 握手，返回 Ok(None)
+/// 如果是握手但不合法，返回应该使用的状态，版本不受支持时是 `426 UPGRADE REQUIRED`
+pub fn check_handshake<T>(request: &HttpRequest<T>) -> Result<Option<String>, &'static str> {
+    let has_token = |k: &str, token: &str| {
+        request
+            .headers()
+            .get_all(k)
+            .flat_map(|a| a.split(','))
+            .any(|a| a.trim().eq_ignore_ascii_case(token))
+    };
+    if !has_token("Upgrade", "websocket") {
+        return Ok(None);
+    }
+    if request.request_method() != "GET"
+        || request.version() != "HTTP/1.1"
+        || !has_token("Connection", "upgrade")
+    {
+        return Err("400 BAD REQUEST");
+    }
+    if request
+        .get_header("Sec-WebSocket-Version")
+        .map(|a| a.as_str())
+        != Some("13")
+    {
+        return Err("426 UPGRADE REQUIRED");
+    }
+    // 密钥是 16 个随机字节的 Base64 编码，参见 RFC 6455 4.1
+    match request.get_header("Sec-WebSocket-Key") {
+        Some(key)
+            if key.len() == 24
+                && key.ends_with("==")
+                && key[..22]
+                    .bytes()
+                    .all(|a| a.is_ascii_alphanumeric() || a == b'+' || a == b'/') =>
+        {
+            Ok(Some(accept_key(key)))
+        }
+        _ => Err("400 BAD REQUEST"),
+    }
+}
+
+/// 从客户端读取一个帧，max_payload 是负载的最大字节数
+pub fn read_frame<R: Read>(reader: &mut R, max_payload: u64) -> Result<Frame, FrameError> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).map_err(|_| FrameError::Io)?;
+    let fin = head[0] & 0x80 != 0;
+    // 没有协商任何扩展，所以保留位必须是 0
+    if head[0] & 0x70 != 0 {
+        return Err(FrameError::Protocol);
+    }
+    let opcode = Opcode::from_u8(head[0] & 0x0f).ok_or(FrameError::Protocol)?;
+    // 客户端发来的帧必须被掩码，参见 RFC 6455 5.1
+    if head[1] & 0x80 == 0 {
+        return Err(FrameError::Protocol);
+    }
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut a = [0u8; 2];
+            reader.read_exact(&mut a).map_err(|_| FrameError::Io)?;
+            u16::from_be_bytes(a) as u64
+        }
+        127 => {
+            let mut a = [0u8; 8];
+            reader.read_exact(&mut a).map_err(|_| FrameError::Io)?;
+            u64::from_be_bytes(a)
+        }
+        a => a as u64,
+    };
+    if opcode.is_control() && (!fin || len > 125) {
+        return Err(FrameError::Protocol);
+    }
+    if len > max_payload {
+        return Err(FrameError::TooLarge);
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).map_err(|_| FrameError::Io)?;
+    let mut payload = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut payload)
+        .map_err(|_| FrameError::Io)?;
+    for (i, a) in payload.iter_mut().enumerate() {
+        *a ^= mask[i % 4];
+    }
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// 向客户端写出一个完整的（不分片的）帧
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    opcode: Opcode,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut head = vec![0x80 | opcode.to_u8()];
+    match payload.len() {
+        a @ 0..=125 => head.push(a as u8),
+        a @ 126..=0xffff => {
+            head.push(126);
+            head.extend_from_slice(&(a as u16).to_be_bytes());
+        }
+        a => {
+            head.push(127);
+            head.extend_from_slice(&(a as u64).to_be_bytes());
+        }
+    }
+    head.extend_from_slice(payload);
+    writer.write_all(&head)?;
+    writer.flush()
+}
+
+/// 关闭帧中的状态码是否可以被发送，参见 RFC 6455 7.4
+/// 1005 、 1006 和 1015 是保留的，它们只用于表示没有状态码或连接异常中断，不能出现在关闭帧中
+pub fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// 写出一个关闭帧，code 是状态码，例如正常关闭时的 1000
+pub fn write_close<W: Write>(writer: &mut W, code: u16) -> std::io::Result<()> {
+    write_frame(writer, Opcode::Close, &code.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn frame() {
+        // RFC 6455 1.3 中的例子
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        // RFC 6455 5.7 中被掩码的 "Hello"
+        let masked = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let frame = read_frame(&mut &masked[..], 125).unwrap();
+        assert!(frame.fin);
+        assert_eq!(
+            (frame.opcode, &frame.payload[..]),
+            (Opcode::Text, &b"Hello"[..])
+        );
+        assert!(matches!(
+            read_frame(&mut &masked[..], 4),
+            Err(FrameError::TooLarge)
+        ));
+        // 没有被掩码
+        assert!(matches!(
+            read_frame(&mut &[0x81, 0x05, b'H'][..], 125),
+            Err(FrameError::Protocol)
+        ));
+
+        let mut out = vec![];
+        write_frame(&mut out, Opcode::Text, b"Hello").unwrap();
+        assert_eq!(out, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+        out.clear();
+        write_frame(&mut out, Opcode::Binary, &[0; 256]).unwrap();
+        assert_eq!(out[..4], [0x82, 126, 0x01, 0x00]);
+
+        assert!(is_valid_close_code(1000) && is_valid_close_code(4000));
+        assert!(![0, 999, 1004, 1005, 1006, 1015, 2000, 5000]
+            .iter()
+            .any(|a| is_valid_close_code(*a)));
+    }
+}
@@ -75,6 +75,37 @@ pub fn disable_stack() {
     unsafe { STACK = None };
 }
 
+thread_local! {
+    /// Pipe 的输出流，send 函数会把字符串写入其中
+    /// 在 Pipe 之外，它是 None
+    static OUTPUT: std::cell::RefCell<Option<Box<dyn std::io::Write>>> =
+        const { std::cell::RefCell::new(None) };
+    /// Pipe 希望加入响应中的响应头，例如 set-cookie 函数设置的 `Set-Cookie`
+    static HEADERS: std::cell::RefCell<Vec<(String, String)>> =
+        const { std::cell::RefCell::new(vec![]) };
+}
+
+pub(super) fn add_header(k: &str, v: String) {
+    HEADERS.with(|a| a.borrow_mut().push((k.to_owned(), v)));
+}
+
+/// 取出并清空所有待加入响应的响应头
+pub fn take_headers() -> Vec<(String, String)> {
+    HEADERS.with(|a| std::mem::take(&mut *a.borrow_mut()))
+}
+
+pub fn set_output(output: Option<Box<dyn std::io::Write>>) {
+    OUTPUT.with(|a| drop(a.replace(output)));
+}
+
+/// 返回值说明了是否写入成功，如果当前没有输出流，也会返回 false
+pub(super) fn write_output(buf: &[u8]) -> bool {
+    OUTPUT.with(|a| match a.borrow_mut().as_mut() {
+        Some(output) => output.write_all(buf).and_then(|_| output.flush()).is_ok(),
+        None => false,
+    })
+}
+
 /// 使用 RefCell 包装是为了包装 `&'a mut crate::config::Config` 以使其可以被正确移动
 /// 使用 Rc 是为了解决在递归式解析中不可避免的循环可变引用
 /// 与其深拷贝一次 Config ，每次调用函数时多进行一次寻址在通常情况下可能更快
@@ -49,3 +49,26 @@ pub fn func_map(
 
     Ok(Expression::List(vec))
 }
+
+/// 在一个由二元列表组成的列表中，找到第一个首元素等于 key 的二元列表，返回它的第二个元素
+/// 例如 `(assoc QUERY "a")` ，如果找不到则返回 false
+pub fn func_assoc(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("assoc", args, 2);
+    args_len_max!("assoc", args, 2);
+
+    let list = check_type_onlyone!("assoc", &args[0], env, List, config.clone())?;
+    let key = eval(&args[1], env, config)?;
+
+    for element in list {
+        if let Expression::List(pair) = element {
+            if pair.first() == Some(&key) {
+                return Ok(pair.get(1).cloned().unwrap_or(Expression::Bool(false)));
+            }
+        }
+    }
+    Ok(Expression::Bool(false))
+}
@@ -15,11 +15,19 @@ pub fn func_serve(
     config: Config,
 ) -> Result<Expression, GError> {
     args_len_min!("serve", args, 3);
-    args_len_max!("serve", args, 3);
+    args_len_max!("serve", args, 4);
 
     let url = check_type_onlyone!("serve", &args[0], env, String, config.clone())?;
     let file_path = check_type_onlyone!("serve", &args[1], env, String, config.clone())?;
     let content_type = check_type_onlyone!("serve", &args[2], env, String, config.clone())?;
+    let methods = if let Some(methods) = args.get(3) {
+        let methods = check_type_onlyone!("serve", methods, env, String, config.clone())?;
+        crate::config::ServeFileData::parse_methods(&methods).ok_or(GError::Reason(
+            "serve: The fourth arg is not a list of methods like \"GET,POST\"".to_owned(),
+        ))?
+    } else {
+        crate::config::ServeFileData::default_methods()
+    };
 
     if !std::path::Path::new(&file_path).is_file() {
         return Err(GError::Reason(
@@ -30,11 +38,13 @@ pub fn func_serve(
     if let Some(_config) = config {
         let mut _config = _config.borrow_mut();
         _config.router_config.serve_files_info.insert(
-            "/".to_owned() + &url,
+            crate::config::route_key(&url),
             crate::config::ServeFileData {
                 file_path: "/../".to_owned() + &file_path,
                 content_type,
                 replace: None,
+                methods,
+                precompressed: false,
             },
         );
         Ok(Expression::Bool(true))
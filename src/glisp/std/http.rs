@@ -0,0 +1,112 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::macros::*;
+use super::*;
+use crate::drop::http::{parse_cookies, SetCookie};
+
+/// 解析 `Cookie` 请求头，返回形如 `(("a" "1") ("b" "2"))` 的列表
+pub fn func_parse_cookie(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("parse-cookie", args, 1);
+    args_len_max!("parse-cookie", args, 1);
+    let str = check_type_onlyone!("parse-cookie", &args[0], env, String, config)?;
+
+    Ok(Expression::List(
+        parse_cookies(&str)
+            .into_iter()
+            .map(|(k, v)| Expression::List(vec![Expression::String(k), Expression::String(v)]))
+            .collect(),
+    ))
+}
+
+/// 由名字、值和可选的属性字符串构造 `Set-Cookie` 的值
+/// 属性字符串的写法和 `Set-Cookie` 中的相同，例如 `Path=/; Max-Age=3600; HttpOnly`
+fn make_cookie(
+    fnname: &str,
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<String, GError> {
+    let name = check_type_onlyone!(fnname, &args[0], env, String, config.clone())?;
+    let value = check_type_onlyone!(fnname, &args[1], env, String, config.clone())?;
+    let mut cookie = SetCookie::new(&name, &value);
+    if let Some(attributes) = args.get(2) {
+        let attributes = check_type_onlyone!(fnname, attributes, env, String, config)?;
+        cookie = cookie
+            .attributes(&attributes)
+            .ok_or(GError::Reason(format!(
+                "{}: The third arg is not a list of attributes like \"Path=/; HttpOnly\"",
+                fnname
+            )))?;
+    }
+    cookie.to_header().ok_or(GError::Reason(format!(
+        "{}: Invalid cookie name, value or attribute",
+        fnname
+    )))
+}
+
+/// 返回 `Set-Cookie` 的值，但不设置它
+pub fn func_make_cookie(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("make-cookie", args, 2);
+    args_len_max!("make-cookie", args, 3);
+
+    Ok(Expression::String(make_cookie(
+        "make-cookie",
+        args,
+        env,
+        config,
+    )?))
+}
+
+/// 在 Pipe 中，向响应追加一个 `Set-Cookie` 响应头
+pub fn func_set_cookie(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("set-cookie", args, 2);
+    args_len_max!("set-cookie", args, 3);
+
+    add_header("Set-Cookie", make_cookie("set-cookie", args, env, config)?);
+    Ok(Expression::Bool(true))
+}
+
+/// 在 SSE 脚本中，发送一个事件，参数依次是 data 和可选的 event 、 id
+/// 设置了 id 时，`LAST-EVENT-ID` 会被更新为它
+pub fn func_emit(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("emit", args, 1);
+    args_len_max!("emit", args, 3);
+    let data = check_type_onlyone!("emit", &args[0], env, String, config.clone())?;
+    let event = match args.get(1) {
+        Some(a) => Some(check_type_onlyone!("emit", a, env, String, config.clone())?),
+        None => None,
+    };
+    let id = match args.get(2) {
+        Some(a) => Some(check_type_onlyone!("emit", a, env, String, config)?),
+        None => None,
+    };
+
+    let event = crate::drop::sse::format_event(&data, event.as_deref(), id.as_deref());
+    if let Some(id) = id {
+        env.data
+            .insert("LAST-EVENT-ID".to_owned(), Expression::String(id));
+    }
+    Ok(Expression::Bool(write_output(event.as_bytes())))
+}
@@ -33,6 +33,20 @@ pub fn func_console_log(
     Ok(Expression::Bool(true))
 }
 
+/// 在 Pipe 中，将一个字符串作为响应主体的一部分立即发送给客户端
+/// 第一次调用时会先发送响应头，之后该请求不会再经过剩下的 Pipe
+pub fn func_send(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("send", args, 1);
+    args_len_max!("send", args, 1);
+    let str = check_type_onlyone!("send", &args[0], env, String, config)?;
+
+    Ok(Expression::Bool(write_output(str.as_bytes())))
+}
+
 pub fn func_read_file(
     args: &[Expression],
     env: &mut Environment,
@@ -49,6 +63,23 @@ pub fn func_read_file(
     }
 }
 
+/// 移动或重命名一个文件，例如将上传的临时文件保存下来
+/// 如果不能直接重命名（例如跨越了文件系统），则先复制再删除原文件
+pub fn func_move_file(
+    args: &[Expression],
+    env: &mut Environment,
+    config: Config,
+) -> Result<Expression, GError> {
+    args_len_min!("move-file", args, 2);
+    args_len_max!("move-file", args, 2);
+    let from = check_type_onlyone!("move-file", &args[0], env, String, config.clone())?;
+    let to = check_type_onlyone!("move-file", &args[1], env, String, config)?;
+
+    let moved = std::fs::rename(&from, &to).is_ok()
+        || (std::fs::copy(&from, &to).is_ok() && std::fs::remove_file(&from).is_ok());
+    Ok(Expression::Bool(moved))
+}
+
 pub fn func_write_file(
     args: &[Expression],
     env: &mut Environment,
@@ -10,6 +10,7 @@ mod algorithm;
 mod config;
 mod core;
 mod eval;
+mod http;
 mod io;
 mod macros;
 mod str;
@@ -23,6 +24,7 @@ use algorithm::*;
 use config::*;
 use core::*;
 use eval::*;
+use http::*;
 use io::*;
 use str::*;
 
@@ -80,6 +82,8 @@ pub fn eval_built_in_form(
             "loop" => Some(func_loop(other_args, env, config)),
             "read-file" => Some(func_read_file(other_args, env, config)),
             "write-file" => Some(func_write_file(other_args, env, config)),
+            "move-file" => Some(func_move_file(other_args, env, config)),
+            "send" => Some(func_send(other_args, env, config)),
             "do" => Some(func_do(other_args, env, config)),
             "meta" => Some(func_meta(other_args, env, config)),
             "eval-atom" => Some(func_eval_atom(other_args, env, config)),
@@ -97,6 +101,7 @@ pub fn eval_built_in_form(
             "run" => Some(func_run(other_args, env, config)),
             "serve" => Some(func_serve(other_args, env, config)),
             "map" => Some(func_map(other_args, env, config)),
+            "assoc" => Some(func_assoc(other_args, env, config)),
             "repl" => Some(func_repl(other_args, env, config)),
             "input" => Some(func_input(other_args, env, config)),
             "fly" => Some(func_fly(other_args, env, config)),
@@ -106,6 +111,10 @@ pub fn eval_built_in_form(
             "format" => Some(func_format(other_args, env, config)),
             "to-num" => Some(func_to_num(other_args, env, config)),
             "pure-length" => Some(func_pure_length(other_args, env, config)),
+            "parse-cookie" => Some(func_parse_cookie(other_args, env, config)),
+            "make-cookie" => Some(func_make_cookie(other_args, env, config)),
+            "set-cookie" => Some(func_set_cookie(other_args, env, config)),
+            "emit" => Some(func_emit(other_args, env, config)),
             _ => None,
         },
         _ => None,
@@ -45,7 +45,27 @@ crate::macros::create_static_string_list!(
     "Return code:",
     "Error:", // 34
     "The pipe only receive string or bool, not: ",
-    "Unsupported status code: "
+    "Unsupported status code: ",
+    "Can not set the read timeout of a TCP stream.", // 37
+    "The request was rejected with: ",
+    "Malformed or ambiguous request: ",
+    "WebSocket connection established.", // 40
+    "Server-Sent Events stream started.",
+    "Internal server error while serving: ", // 42
+    "This is not a directory. ",
+    "Refused to serve a path outside the roots or through a symlink: ", // 44
+    "Configuration reloaded.",
+    "Failed to reload the configuration, the old configuration is kept. Number of errors: ", // 46
+    "Shutting down, no longer accepting new connections.",
+    "Shutdown timeout reached, connections dropped: ",
+    "Server stopped.", // 49
+    "Worker threads (index, busy, connections handled, busy milliseconds). Queued connections: ",
+    "Worker thread: ", // 51
+    "Can not create the epoll instance.",
+    "event-mode is only supported on Linux, the normal mode is used instead.", // 53
+    "Listening on: ",
+    "HTTPS listeners are only available in the nightly build. ", // 55
+    "sse-interval must be at least 1 second. " // 56
 );
 
 #[cfg(feature = "chinese")]
@@ -87,5 +107,25 @@ crate::macros::create_static_string_list!(
     "返回码:",
     "错误:", // 34
     "Pipe 只接收字符串或布尔值，不接收: ",
-    "不支持的状态码: "
+    "不支持的状态码: ",
+    "无法设置 TCP 流的读取超时.", // 37
+    "请求已被拒绝并返回: ",
+    "格式错误或有歧义的请求: ",
+    "WebSocket 连接已建立.", // 40
+    "Server-Sent Events 事件流已开始.",
+    "处理请求时发生内部错误: ", // 42
+    "这不是一个目录. ",
+    "已拒绝发送位于 roots 之外或经过符号链接的路径: ", // 44
+    "配置已重新加载.",
+    "重新加载配置失败, 旧的配置被保留. 错误数量: ", // 46
+    "正在关闭, 不再接受新的连接.",
+    "关闭超时, 被中断的连接数量: ",
+    "服务器已停止.", // 49
+    "工作线程 (序号, 是否忙碌, 处理过的连接数, 忙碌的毫秒数). 等待中的连接数: ",
+    "工作线程: ", // 51
+    "无法创建 epoll 实例.",
+    "event-mode 只支持 Linux, 将使用普通模式.", // 53
+    "正在监听: ",
+    "HTTPS 监听器只在 nightly 版本中可用. ", // 55
+    "sse-interval 至少为 1 秒. " // 56
 );
\ No newline at end of file
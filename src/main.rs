@@ -48,8 +48,14 @@ fn main() {
         );
     }
     let config = config_init();
+    config::reload::watch();
 
-    if config::BOX_MODE.load(std::sync::atomic::Ordering::Relaxed) {
+    if config::EVENT_MODE.load(std::sync::atomic::Ordering::Relaxed) {
+        #[cfg(target_os = "linux")]
+        mode::eventmode::start(config);
+        #[cfg(not(target_os = "linux"))]
+        log!(Warn, LOG[53]);
+    } else if config::BOX_MODE.load(std::sync::atomic::Ordering::Relaxed) {
         mode::boxmode::start(config);
     }
 
@@ -57,6 +63,7 @@ fn main() {
 }
 
 pub fn config_init() -> Config {
+    config::reload::save_defaults();
     let config: Config = match crate::config::read_config("main.gc".to_owned(), &mut Config::new())
     {
         Ok(config) => config.clone(),
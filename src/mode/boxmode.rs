@@ -6,6 +6,7 @@
  * if not, see <https://www.gnu.org/licenses/>.
  */
 
+use super::shutdown::{self, InFlight};
 use super::utils::*;
 use crate::drop::log::LogLevel::*;
 use crate::drop::thread::ThreadPool;
@@ -15,7 +16,6 @@ use crate::{config::*, drop::time::Time, i18n::LOG};
 use std::{
     collections::VecDeque,
     net::TcpStream,
-    process::exit,
     sync::{atomic::Ordering, Arc, Mutex},
 };
 
@@ -30,7 +30,11 @@ struct StreamResultCounters {
     new_stamp_timeout: i16,
 }
 
-static mut THREADS_BOX: Option<Arc<Mutex<VecDeque<std::net::TcpStream>>>> = None;
+/// 已经被接受但还没有被处理的连接
+/// bool: 这个连接是否来自一个 https 的监听器
+type ThreadsBox = Mutex<VecDeque<(std::net::TcpStream, bool, InFlight)>>;
+
+static mut THREADS_BOX: Option<Arc<ThreadsBox>> = None;
 
 /// 在这个模式中，我们会构造一个计数器
 /// 计数器会统计 N 秒内的请求数量
@@ -41,12 +45,19 @@ static mut THREADS_BOX: Option<Arc<Mutex<VecDeque<std::net::TcpStream>>>> = None
 pub fn start(config: Config) -> ! {
     log!(Info, LOG[15]);
 
-    let listener = listener_init(config);
+    let listeners = listener_init(config);
+    shutdown::init(&listeners);
 
-    let mut threadpool = ThreadPool::new();
+    let threadpool = ThreadPool::new(
+        THREADS_NUM.load(Ordering::Relaxed) as usize,
+        QUEUE_DEPTH.load(Ordering::Relaxed) as usize,
+    );
+    stats_init(&threadpool);
 
-    if listener.set_nonblocking(true).is_err() {
-        log!(Warn, LOG[26])
+    for (listener, _) in &listeners {
+        if listener.set_nonblocking(true).is_err() {
+            log!(Warn, LOG[26])
+        }
     }
 
     let box_num_per_thread_mag = BOX_NUM_PER_THREAD_MAG.load(Ordering::Relaxed) as f32 / 1000.0;
@@ -64,61 +75,99 @@ pub fn start(config: Config) -> ! {
         old_stamp_timeout: Time::msec().result_timeerr_default(),
         new_stamp_timeout: Time::msec().result_timeerr_default(),
     };
-    unsafe { THREADS_BOX = Some(Arc::new(Mutex::new(VecDeque::new()))) };
+    let threads_box = Arc::new(Mutex::new(VecDeque::new()));
+    unsafe { THREADS_BOX = Some(threads_box.clone()) };
+    let queue_depth = QUEUE_DEPTH.load(Ordering::Relaxed) as usize;
+    // 线程池的队列已满时，已经有足够多的任务在等待处理 THREADS_BOX ，这时不再添加任务，以免阻塞接受连接的循环
     macro_rules! clear_threads_cache {
         () => {
-            let func = move || {
-                let mut i = 0;
-                while i != (counters.box_num_per_thread as f32 * box_num_per_thread_mag) as u32 {
-                    handle_connection_s(unsafe { &THREADS_BOX.clone().unwrap() }, unsafe {
-                        &GLOBAL_ROUTER_CONFIG.as_ref().unwrap().clone()
-                    });
-                    i += 1;
-                }
-            };
-            threadpool.add(threads_num.try_into().unwrap(), func);
+            if !threadpool.is_full() {
+                let func = move || {
+                    let mut i = 0;
+                    while i != (counters.box_num_per_thread as f32 * box_num_per_thread_mag) as u32
+                    {
+                        handle_connection_s(
+                            unsafe { &THREADS_BOX.clone().unwrap() },
+                            &router_config(),
+                        );
+                        i += 1;
+                    }
+                };
+                threadpool.add(func);
+            }
         };
     }
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                ok_vars_init(&mut counters);
-
-                if thread_box_add(&stream).is_err() {
-                    continue;
+    // 依次检查每一个监听器，所有的监听器共用同一个 THREADS_BOX
+    'accept: loop {
+        for (listener, bind) in &listeners {
+            if shutdown::is_shutting_down() {
+                break 'accept;
+            }
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    ok_vars_init(&mut counters);
+
+                    // THREADS_BOX 的长度被 queue-depth 限制，参见 normalmode
+                    if threads_box_len(&threads_box) >= queue_depth {
+                        if QUEUE_OVERFLOW_REJECT.load(Ordering::Relaxed) {
+                            write_busy_response(&stream, &router_config());
+                            continue;
+                        }
+                        while threads_box_len(&threads_box) >= queue_depth {
+                            let threads_box = threads_box.clone();
+                            threadpool.add(move || {
+                                handle_connection_s(&threads_box, &router_config());
+                            });
+                        }
+                    }
+
+                    if thread_box_add(&stream, bind.https).is_err() {
+                        continue;
+                    }
+
+                    log!(Debug, format!("{}{:#?}\n", LOG[3], stream));
+                    if is_nst_gt_ost_timeout(
+                        &counters.old_stamp_timeout,
+                        &counters.new_stamp_timeout,
+                    ) {
+                        if_new_tick_start(&mut counters, xrps_predict_mag);
+                        clear_threads_cache!();
+                        counters.old_stamp_timeout = counters.new_stamp_timeout;
+                    }
                 }
-
-                log!(Debug, format!("{}{:#?}\n", LOG[3], stream));
-                if is_nst_gt_ost_timeout(&counters.old_stamp_timeout, &counters.new_stamp_timeout) {
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    err_vars_init(&mut counters);
                     if_new_tick_start(&mut counters, xrps_predict_mag);
-                    clear_threads_cache!();
-                    counters.old_stamp_timeout = counters.new_stamp_timeout;
-                }
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                err_vars_init(&mut counters);
-                if_new_tick_start(&mut counters, xrps_predict_mag);
-                if counters.flag_new_box_num
-                    || is_nst_gt_ost_timeout(&counters.old_stamp, &counters.new_stamp)
-                {
-                    clear_threads_cache!();
-                    counters.box_num_per_thread =
-                        (threads_num as f32 * box_num_per_thread_init_mag) as u32;
+                    if counters.flag_new_box_num
+                        || is_nst_gt_ost_timeout(&counters.old_stamp, &counters.new_stamp)
+                    {
+                        clear_threads_cache!();
+                        counters.box_num_per_thread =
+                            (threads_num as f32 * box_num_per_thread_init_mag) as u32;
+                    }
+                    continue;
                 }
-                continue;
+                _ => log!(Error, LOG[2]),
             }
-            _ => log!(Error, LOG[2]),
         }
     }
-    exit(0);
+    // 已经被放入 THREADS_BOX 但还没有被处理的连接也需要在关闭之前被处理
+    std::thread::spawn(move || while handle_connection_s(&threads_box, &router_config()) {});
+    shutdown::drain_and_exit();
 }
 
-fn handle_connection_s(streams: &Mutex<VecDeque<std::net::TcpStream>>, config: &RouterConfig) {
-    let stream = match streams.lock().unwrap().pop_front() {
+/// 返回值说明了 streams 中是否还有连接
+fn handle_connection_s(streams: &ThreadsBox, config: &RouterConfig) -> bool {
+    let (stream, https, _in_flight) = match streams.lock().unwrap().pop_front() {
         Some(a) => a,
-        _ => return,
+        _ => return false,
     };
-    handle_connection(stream, config);
+    handle_connection(stream, config, https);
+    true
+}
+
+fn threads_box_len(streams: &ThreadsBox) -> usize {
+    streams.lock().unwrap().len()
 }
 
 fn err_vars_init(counters: &mut StreamResultCounters) {
@@ -143,7 +192,7 @@ fn if_new_tick_start(counters: &mut StreamResultCounters, xrps_predict_mag: f32)
     }
 }
 
-fn thread_box_add(stream: &TcpStream) -> Result<(), ()> {
+fn thread_box_add(stream: &TcpStream, https: bool) -> Result<(), ()> {
     unsafe {
         THREADS_BOX
             .as_ref()
@@ -152,7 +201,7 @@ fn thread_box_add(stream: &TcpStream) -> Result<(), ()> {
             .lock()
             .unwrap()
             .push_back(if let Ok(a) = stream.try_clone() {
-                a
+                (a, https, InFlight::new())
             } else {
                 log!(Warn, LOG[27]);
                 return Err(());
@@ -0,0 +1,220 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 基于 epoll 的事件循环模式，只在 Linux 上可用
+//! 空闲的持久连接不会占用任何线程：它们被交给 epoll 监听，只有在请求到达时，才会被交给线程池处理
+//! 已经到达的请求都被处理完之后，连接又会被交还给 epoll ，所以少量的线程就可以维持大量的空闲连接
+//! 请求本身的处理与其它模式完全相同，参见 utils::serve_ready_requests
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::shutdown::{self, InFlight};
+use super::utils::*;
+use crate::{
+    config::{
+        router_config, Config, RouterConfig, HEADER_TIMEOUT, KEEP_ALIVE_TIMEOUT, QUEUE_DEPTH,
+        QUEUE_OVERFLOW_REJECT, THREADS_NUM,
+    },
+    drop::{
+        epoll::{Epoll, EPOLLIN, EPOLLONESHOT, EPOLLRDHUP},
+        log::LogLevel::*,
+        thread::ThreadPool,
+        tool::ShouldResult,
+    },
+    i18n::LOG,
+    macros::*,
+};
+
+/// 第 i 个监听器的 token 是 LISTENER - i ，连接的 token 是它的文件描述符
+const LISTENER: u64 = u64::MAX;
+
+/// 一个正在等待下一个请求的连接
+/// served: 这个连接已经处理过的请求的数量
+/// since: 从何时开始等待，用于关闭空闲得太久的连接
+struct Parked {
+    stream: TcpStream,
+    served: u32,
+    since: Instant,
+}
+type ParkedMap = Mutex<HashMap<u64, Parked>>;
+
+pub fn start(config: Config) -> ! {
+    log!(Info, LOG[15]);
+
+    let listeners = listener_init(config);
+    shutdown::init(&listeners);
+
+    let epoll = Arc::new(Epoll::new().result_shldfatal(-1, || log!(Fatal, LOG[52])));
+    for (i, (listener, _)) in listeners.iter().enumerate() {
+        if listener.set_nonblocking(true).is_err() {
+            log!(Warn, LOG[26])
+        }
+        epoll
+            .add(listener.as_raw_fd(), LISTENER - i as u64, EPOLLIN)
+            .result_shldfatal(-1, || log!(Fatal, LOG[52]));
+    }
+    let parked: Arc<ParkedMap> = Arc::new(Mutex::new(HashMap::new()));
+
+    let threadpool = ThreadPool::new(
+        THREADS_NUM.load(Ordering::Relaxed) as usize,
+        QUEUE_DEPTH.load(Ordering::Relaxed) as usize,
+    );
+    stats_init(&threadpool);
+
+    // 线程池已满时，请求已经到达的连接仍然留在 parked 中，但不再被 epoll 监听，直到线程池有空位
+    let mut deferred: VecDeque<u64> = VecDeque::new();
+    loop {
+        // 至少每秒醒来一次，以便关闭空闲的连接和检查是否正在关闭
+        // 有被推迟的连接时则更频繁的醒来，以便尽快的处理它们
+        let timeout = if deferred.is_empty() { 1000 } else { 10 };
+        let events = epoll.wait(1024, timeout).unwrap_or_default();
+        if shutdown::is_shutting_down() {
+            break;
+        }
+        while !deferred.is_empty() && !threadpool.is_full() {
+            let token = deferred.pop_front().unwrap();
+            dispatch(token, &threadpool, &epoll, &parked);
+        }
+        for (token, _) in events {
+            if let Some((listener, bind)) = listeners.get((LISTENER - token) as usize) {
+                accept_all(listener, bind.https, &threadpool, &epoll, &parked);
+                continue;
+            }
+            // 不能在这里阻塞的等待线程池，否则所有的连接都会停止
+            if threadpool.is_full() || !deferred.is_empty() {
+                if QUEUE_OVERFLOW_REJECT.load(Ordering::Relaxed) {
+                    if let Some(conn) = lock(&parked).remove(&token) {
+                        write_busy_response(&conn.stream, &router_config());
+                    }
+                } else {
+                    deferred.push_back(token);
+                }
+                continue;
+            }
+            dispatch(token, &threadpool, &epoll, &parked);
+        }
+        close_idle(&parked);
+    }
+    // 等待中的连接没有正在处理的请求，可以直接关闭
+    lock(&parked).clear();
+    shutdown::drain_and_exit();
+}
+
+/// 把请求已经到达的连接交给线程池处理，调用者需要确保线程池没有满
+fn dispatch(token: u64, threadpool: &ThreadPool, epoll: &Arc<Epoll>, parked: &Arc<ParkedMap>) {
+    let conn = match lock(parked).remove(&token) {
+        Some(a) => a,
+        // 可能已经因为空闲得太久而被关闭
+        None => return,
+    };
+    let config = router_config();
+    let (epoll, parked) = (epoll.clone(), parked.clone());
+    let in_flight = InFlight::new();
+    threadpool.add(move || {
+        let _in_flight = in_flight;
+        serve(conn, &config, &epoll, &parked);
+    });
+}
+
+fn lock(parked: &ParkedMap) -> std::sync::MutexGuard<'_, HashMap<u64, Parked>> {
+    parked.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 接受所有正在等待的连接，它们在第一个请求到达之前同样由 epoll 监听
+/// https 的连接不会被交给 epoll ，而是直接由线程池处理，参见 utils::handle_connection
+fn accept_all(
+    listener: &TcpListener,
+    https: bool,
+    threadpool: &ThreadPool,
+    epoll: &Epoll,
+    parked: &ParkedMap,
+) {
+    loop {
+        let stream = match listener.accept() {
+            Ok((a, _)) => a,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+            Err(_) => {
+                log!(Warn, LOG[4]);
+                return;
+            }
+        };
+        log!(Debug, format!("{}{:#?}\n", LOG[3], stream));
+        // 请求会在线程池中被阻塞的读取
+        if stream.set_nonblocking(false).is_err() {
+            continue;
+        }
+        if threadpool.is_full() && QUEUE_OVERFLOW_REJECT.load(Ordering::Relaxed) {
+            write_busy_response(&stream, &router_config());
+            continue;
+        }
+        if https {
+            let config = router_config();
+            let in_flight = InFlight::new();
+            threadpool.add(move || {
+                let _in_flight = in_flight;
+                handle_connection(stream, &config, true)
+            });
+            continue;
+        }
+        park(stream, 0, epoll, parked);
+    }
+}
+
+/// 处理已经到达的请求，如果连接仍然保持打开，则把它交还给 epoll
+fn serve(mut conn: Parked, config: &RouterConfig, epoll: &Epoll, parked: &ParkedMap) {
+    if serve_ready_requests(&conn.stream, config, &mut conn.served) && !shutdown::is_shutting_down()
+    {
+        park(conn.stream, conn.served, epoll, parked);
+    }
+}
+
+/// 连接必须先被放入 parked ，再被 epoll 监听，否则它的事件可能在被放入之前就已经被取走
+/// 每一个事件只会被返回一次 (EPOLLONESHOT) ，所以同一个连接不会同时被两个线程处理
+fn park(stream: TcpStream, served: u32, epoll: &Epoll, parked: &ParkedMap) {
+    let fd = stream.as_raw_fd();
+    let token = fd as u64;
+    lock(parked).insert(
+        token,
+        Parked {
+            stream,
+            served,
+            since: Instant::now(),
+        },
+    );
+    let events = EPOLLIN | EPOLLRDHUP | EPOLLONESHOT;
+    // 新的连接需要被添加，而处理过请求的连接已经被添加过了，只需要被重新启用
+    let res = if served == 0 {
+        epoll.add(fd, token, events)
+    } else {
+        epoll.modify(fd, token, events)
+    };
+    if res.is_err() {
+        lock(parked).remove(&token);
+    }
+}
+
+/// 第一个请求之前，以及两个请求之间，连接分别最多等待 header-timeout 和 keep-alive-timeout 秒
+/// 被关闭的文件描述符会自动的从 epoll 中被移除
+fn close_idle(parked: &ParkedMap) {
+    let header_timeout = HEADER_TIMEOUT.load(Ordering::Relaxed);
+    let keep_alive_timeout = KEEP_ALIVE_TIMEOUT.load(Ordering::Relaxed).max(1);
+    lock(parked).retain(|_, a| {
+        let timeout = match a.served {
+            0 if header_timeout == 0 => return true,
+            0 => header_timeout,
+            _ => keep_alive_timeout,
+        };
+        a.since.elapsed() < Duration::from_secs(timeout.into())
+    });
+}
@@ -7,6 +7,13 @@
  */
 
 pub mod boxmode;
+#[cfg(target_os = "linux")]
+pub mod eventmode;
 pub mod normalmode;
+mod shutdown;
 pub mod toolmode;
+#[cfg(not(feature = "no-glisp"))]
+mod sse;
 mod utils;
+#[cfg(not(feature = "no-glisp"))]
+mod websocket;
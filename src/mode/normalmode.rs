@@ -6,10 +6,11 @@
  * if not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{process::exit, sync::atomic::Ordering};
+use std::{net::TcpListener, sync::atomic::Ordering};
 
+use super::shutdown::{self, InFlight};
 use super::utils::*;
-use crate::config::Config;
+use crate::config::{router_config, Config, QUEUE_DEPTH, QUEUE_OVERFLOW_REJECT, THREADS_NUM};
 use crate::drop::log::LogLevel::*;
 use crate::drop::thread::ThreadPool;
 use crate::i18n::LOG;
@@ -18,22 +19,45 @@ use crate::macros::*;
 pub fn start(config: Config) -> ! {
     log!(Info, LOG[15]);
 
-    let listener = listener_init(config);
+    let listeners = listener_init(config);
+    shutdown::init(&listeners);
 
-    let mut threadpool = ThreadPool::new();
+    let threadpool = ThreadPool::new(
+        THREADS_NUM.load(Ordering::Relaxed) as usize,
+        QUEUE_DEPTH.load(Ordering::Relaxed) as usize,
+    );
+    stats_init(&threadpool);
 
-    let threads_num = crate::config::THREADS_NUM.load(Ordering::Relaxed);
+    // 每一个监听器都有一个自己的线程来接受连接，它们共用同一个线程池
+    let accept_threads: Vec<_> = listeners
+        .into_iter()
+        .map(|(listener, bind)| {
+            let threadpool = threadpool.clone();
+            std::thread::spawn(move || accept_loop(listener, bind.https, threadpool))
+        })
+        .collect();
+    for a in accept_threads {
+        let _ = a.join();
+    }
+    shutdown::drain_and_exit();
+}
 
+fn accept_loop(listener: TcpListener, https: bool, threadpool: ThreadPool) {
     for stream in listener.incoming() {
+        if shutdown::is_shutting_down() {
+            break;
+        }
         match stream {
             Ok(req) => {
-                threadpool.add(threads_num.try_into().unwrap(), || {
-                    handle_connection(req, unsafe {
-                        &crate::config::GLOBAL_ROUTER_CONFIG
-                            .as_ref()
-                            .unwrap()
-                            .clone()
-                    })
+                let config = router_config();
+                if threadpool.is_full() && QUEUE_OVERFLOW_REJECT.load(Ordering::Relaxed) {
+                    write_busy_response(&req, &config);
+                    continue;
+                }
+                let in_flight = InFlight::new();
+                threadpool.add(move || {
+                    let _in_flight = in_flight;
+                    handle_connection(req, &config, https)
                 });
             }
             Err(_) => {
@@ -42,5 +66,4 @@ pub fn start(config: Config) -> ! {
             }
         }
     }
-    exit(0);
 }
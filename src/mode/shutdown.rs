@@ -0,0 +1,102 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! 优雅的关闭
+//! 收到 SIGTERM 或 SIGINT 之后，服务器不再接受新的连接，持久连接在当前的请求完成之后会被关闭
+//! 正在处理的连接有 shutdown-timeout 秒的时间完成，之后服务器会以状态码 0 退出
+
+use std::{
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    process::exit,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{AddrBind, SHUTDOWN_TIMEOUT},
+    drop::{
+        log::LogLevel::*,
+        signal::{self, SIGINT, SIGTERM},
+    },
+    i18n::LOG,
+    macros::*,
+};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicU32 = AtomicU32::new(0); // 已经被接受但还没有处理完的连接的数量
+
+pub(super) fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// 一个已经被接受的连接，它被丢弃时，这个连接才算处理完成
+/// 它应该在接受连接时被创建，并被一直带到处理这个连接的线程中
+pub(super) struct InFlight;
+impl InFlight {
+    pub(super) fn new() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 开始监听 SIGTERM 和 SIGINT
+/// 监听器可能正阻塞在 accept 上，所以收到信号时会连接每一个监听器一次以唤醒它们，被唤醒的循环应该检查 is_shutting_down
+pub(super) fn init(listeners: &[(TcpListener, AddrBind)]) {
+    signal::listen(SIGTERM);
+    signal::listen(SIGINT);
+    let wake_addrs: Vec<SocketAddr> = listeners
+        .iter()
+        .filter_map(|(listener, _)| listener.local_addr().ok())
+        .map(|mut a| {
+            if a.ip().is_unspecified() {
+                a.set_ip(match a.ip() {
+                    IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            a
+        })
+        .collect();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(100));
+        if signal::take(SIGTERM) | signal::take(SIGINT) {
+            SHUTTING_DOWN.store(true, Ordering::SeqCst);
+            log!(Info, LOG[47]);
+            for a in &wake_addrs {
+                let _ = TcpStream::connect_timeout(a, Duration::from_secs(1));
+            }
+            return;
+        }
+    });
+}
+
+/// 等待正在处理的连接完成，然后退出
+/// 最多等待 shutdown-timeout 秒，再次收到 SIGTERM 或 SIGINT 时则立即退出
+pub(super) fn drain_and_exit() -> ! {
+    let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT.load(Ordering::Relaxed).into());
+    let start = Instant::now();
+    while IN_FLIGHT.load(Ordering::SeqCst) != 0 && start.elapsed() < timeout {
+        if signal::take(SIGTERM) | signal::take(SIGINT) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let dropped = IN_FLIGHT.load(Ordering::SeqCst);
+    if dropped != 0 {
+        log!(Warn, format!("{}{}", LOG[48], dropped));
+    }
+    log!(Info, LOG[49]);
+    let _ = std::io::stdout().flush();
+    exit(0);
+}
@@ -0,0 +1,217 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Server-Sent Events 连接的处理
+//! 响应头被立即发送，之后这个连接会被一直占用，直到客户端断开或者脚本结束了事件流
+
+use std::{
+    cell::Cell,
+    io::{Read, Seek, Write},
+    net::TcpStream,
+    rc::Rc,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{SseSource, SSE_HEARTBEAT, SSE_INTERVAL},
+    drop::{
+        http::{ChunkedWriter, HttpResponse},
+        log::LogLevel::*,
+        sse::{format_comment, format_event},
+    },
+    glisp::core::Expression,
+    i18n::LOG,
+    macros::*,
+    utils::TimeErr,
+};
+
+/// request_env: 与 Pipe 相同的，关于当前请求的变量，参见 utils::pipe_request_env
+/// last_event_id: 请求中的 `Last-Event-ID` ，浏览器在重新连接时会带上它，以便从中断处继续
+/// chunked: 是否使用分块传输编码，HTTP/1.0 的客户端只能通过关闭连接来得知事件流的结束
+pub(super) fn serve(
+    source: &SseSource,
+    request_env: &[(&str, Expression)],
+    last_event_id: Option<String>,
+    stream: &TcpStream,
+    chunked: bool,
+) {
+    let mut response = HttpResponse::new();
+    response
+        .set_default_headers("Tiny-Tiny-Web/2")
+        .result_timeerr_default();
+    response.set_version("HTTP/1.1");
+    response.set_state("200 OK");
+    response.set_header("Content-Type", "text/event-stream".to_owned());
+    response.set_header("Cache-Control", "no-cache".to_owned());
+    response.set_header("Connection", "close".to_owned());
+    response.set_content_stream(
+        std::sync::Arc::new(std::sync::Mutex::new(std::io::empty())),
+        None,
+    );
+    if !chunked {
+        response.remove_header("Transfer-Encoding");
+    }
+
+    let output = match stream.try_clone() {
+        Ok(a) => SseOutput {
+            stream: a,
+            chunked,
+            last_write: Rc::new(Cell::new(Instant::now())),
+            failed: Rc::new(Cell::new(false)),
+        },
+        Err(_) => return,
+    };
+    if (&*stream).write_all(&response.get_head_stream()).is_err() {
+        return;
+    }
+    log!(Debug, LOG[41]);
+
+    match source {
+        SseSource::Script(script) => {
+            serve_script(script, request_env, last_event_id, output);
+            crate::glisp::core::set_output(None);
+        }
+        SseSource::Watch(path) => serve_watch(path, last_event_id, output),
+    }
+}
+
+/// 每隔 sse-interval 秒执行一次脚本，脚本可以使用 emit 函数发送事件
+/// 脚本返回的字符串会作为一个只有 data 的事件被发送，返回 false 会结束事件流
+///
+/// 同一个连接中的每一次执行共用一个环境，所以脚本可以用 set 保存状态
+fn serve_script(
+    script: &str,
+    request_env: &[(&str, Expression)],
+    last_event_id: Option<String>,
+    mut output: SseOutput,
+) {
+    let env = &mut crate::glisp::core::default_env();
+    for (k, v) in request_env {
+        env.data.insert(k.to_string(), v.clone());
+    }
+    env.data.insert(
+        "LAST-EVENT-ID".to_owned(),
+        Expression::String(last_event_id.unwrap_or_default()),
+    );
+    match output.try_clone() {
+        Some(a) => crate::glisp::core::set_output(Some(Box::new(a))),
+        None => return,
+    }
+
+    loop {
+        match crate::glisp::core::parse_eval(script.to_owned(), env, None) {
+            Ok(Expression::String(res)) if !res.is_empty() => {
+                let _ = output.write_all(format_event(&res, None, None).as_bytes());
+            }
+            Ok(Expression::Bool(false)) => return output.finish(),
+            Ok(Expression::String(_)) | Ok(Expression::Bool(_)) => (),
+            Ok(a) => log!(Error, format!("[{}] {} {}", LOG[32], LOG[35], a)),
+            Err(crate::glisp::core::GError::Reason(msg)) => {
+                log!(Info, format!("[{}] {} {}", LOG[32], LOG[34], msg))
+            }
+        }
+        if !output.wait() {
+            return;
+        }
+    }
+}
+
+/// 每隔 sse-interval 秒检查一次文件，新增的每一个完整的行都会作为一个事件被发送
+/// 事件的 id 是这一行结束处在文件中的偏移量，所以重新连接的浏览器可以从中断处继续
+/// 没有 `Last-Event-ID` 时，只发送连接之后新增的行，文件变短时，则认为它被重写了，从头开始读取
+fn serve_watch(path: &str, last_event_id: Option<String>, mut output: SseOutput) {
+    let len = || std::fs::metadata(path).map_or(0, |a| a.len());
+    let mut offset = match last_event_id.and_then(|a| a.parse().ok()) {
+        Some(a) if a <= len() => a,
+        _ => len(),
+    };
+
+    loop {
+        let len = len();
+        if len < offset {
+            offset = 0;
+        }
+        if len > offset {
+            let mut buf = vec![];
+            if let Ok(mut file) = std::fs::File::open(path) {
+                if file.seek(std::io::SeekFrom::Start(offset)).is_ok() {
+                    let _ = file.take(len - offset).read_to_end(&mut buf);
+                }
+            }
+            // 还没有写完的最后一行留到下一次
+            let complete = buf.iter().rposition(|a| *a == b'\n').map_or(0, |a| a + 1);
+            for line in buf[..complete].split_inclusive(|a| *a == b'\n') {
+                offset += line.len() as u64;
+                let line = String::from_utf8_lossy(line);
+                let event = format_event(
+                    line.trim_end_matches(['\r', '\n']),
+                    None,
+                    Some(&offset.to_string()),
+                );
+                let _ = output.write_all(event.as_bytes());
+            }
+        }
+        if !output.wait() {
+            return;
+        }
+    }
+}
+
+/// 事件流的输出，它记录了最后一次写出的时间，以便在空闲时发送心跳
+struct SseOutput {
+    stream: TcpStream,
+    chunked: bool,
+    last_write: Rc<Cell<Instant>>,
+    failed: Rc<Cell<bool>>,
+}
+impl SseOutput {
+    fn try_clone(&self) -> Option<Self> {
+        Some(SseOutput {
+            stream: self.stream.try_clone().ok()?,
+            chunked: self.chunked,
+            last_write: self.last_write.clone(),
+            failed: self.failed.clone(),
+        })
+    }
+    /// 等待 sse-interval 秒，如果在此期间空闲得太久，则发送一个心跳
+    /// 返回值说明了连接是否仍然可用
+    fn wait(&mut self) -> bool {
+        std::thread::sleep(Duration::from_secs(
+            SSE_INTERVAL.load(Ordering::Relaxed).into(),
+        ));
+        let heartbeat = SSE_HEARTBEAT.load(Ordering::Relaxed);
+        if heartbeat != 0 && self.last_write.get().elapsed().as_secs() >= heartbeat.into() {
+            let _ = self.write_all(format_comment("heartbeat").as_bytes());
+        }
+        !self.failed.get()
+    }
+    /// 正常的结束事件流
+    fn finish(mut self) {
+        if self.chunked && ChunkedWriter::new(&mut self.stream).finish().is_err() {
+            log!(Debug, LOG[6])
+        }
+    }
+}
+impl Write for SseOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let res = if self.chunked {
+            ChunkedWriter::new(&mut self.stream).write(buf)
+        } else {
+            self.stream.write(buf)
+        };
+        match res {
+            Ok(_) => self.last_write.set(Instant::now()),
+            Err(_) => self.failed.set(true),
+        }
+        res
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -15,18 +15,28 @@ use std::{
 
 use crate::{
     config::{
-        Config, RouterConfig, ENABLE_CODE_BAD_REQUEST, SSL_CERTIFICATE, SSL_PRIVATE_KEY,
-        XRPS_COUNTER_CACHE_SIZE,
+        AddrBind, Config, RouterConfig, BODY_TIMEOUT, COMPRESS_MIN_SIZE, ENABLE_CODE_BAD_REQUEST,
+        ENABLE_KEEP_ALIVE, HEADER_TIMEOUT, KEEP_ALIVE_MAX, KEEP_ALIVE_TIMEOUT, MAX_BODY_SIZE,
+        MAX_HEADERS, MAX_HEADER_SIZE, MAX_REQUEST_LINE, SSL_CERTIFICATE, SSL_PRIVATE_KEY,
+        STRICT_PARSING, XRPS_COUNTER_CACHE_SIZE,
     },
     drop::{
-        http::{HttpRequest, HttpResponse},
+        deflate,
+        http::{
+            check_strict, negotiate_encoding, ChunkedReader, HttpContent, HttpRequest,
+            HttpResponse, HttpStrictError,
+        },
         log::LogLevel::*,
         random::*,
+        signal::{self, SIGUSR1},
+        thread::ThreadPool,
         time::Time,
+        tool::ShouldResult,
     },
     https::{ecc::ecdsa_sign, sha256, tls::*},
     i18n::LOG,
     macros::*,
+    router::{new_request_id, request_id, set_error_page},
     utils::TimeErr,
 };
 use std::collections::VecDeque;
@@ -56,118 +66,563 @@ impl ReqCounter {
     }
 }
 
-pub fn listener_init(config: Config) -> TcpListener {
-    let socket_addresses: Vec<std::net::SocketAddr> = config
+/// 为每一个 `+addr` 创建一个独立的监听器，任何一个地址无法被监听都是致命的错误
+pub fn listener_init(config: Config) -> Vec<(TcpListener, AddrBind)> {
+    if config.addr_bind.is_empty() {
+        log!(Fatal, LOG[1]);
+        exit(-1);
+    }
+    config
         .addr_bind
-        .iter()
-        .map(|address| {
-            crate::drop::tool::ShouldResult::result_shldfatal(
-                std::net::ToSocketAddrs::to_socket_addrs(&address),
-                -1,
-                || log!(Fatal, format!("{}{}", LOG[28], address)),
-            )
-            .next()
-            .unwrap()
+        .into_iter()
+        .map(|bind| {
+            let address = std::net::ToSocketAddrs::to_socket_addrs(&bind.addr)
+                .ok()
+                .and_then(|mut a| a.next())
+                .result_shldfatal(-1, || log!(Fatal, format!("{}{}", LOG[28], bind.addr)));
+            let listener = crate::drop::socket::bind(address, bind.backlog);
+            let listener =
+                process_result!(listener, TcpListener, format!("{}{}", LOG[1], bind.addr));
+            log!(
+                Info,
+                format!(
+                    "{}{} ({}, backlog {})",
+                    LOG[54],
+                    listener.local_addr().unwrap_or(address),
+                    if bind.https { "https" } else { "http" },
+                    bind.backlog
+                )
+            );
+            (listener, bind)
         })
-        .collect();
-
-    let socket_addresses_array: &[std::net::SocketAddr] = socket_addresses.as_slice();
-    let listener = TcpListener::bind(socket_addresses_array);
-
-    process_result!(
-        listener,
-        TcpListener,
-        format!("{}{:#?}", LOG[1], config.addr_bind)
-    )
+        .collect()
 }
 
-#[allow(unused_mut)]
-pub fn handle_connection(mut stream: std::net::TcpStream, config: &RouterConfig) {
+/// https: 这个连接是否来自一个 https 的监听器，参见 AddrBind
+#[allow(unused_variables)]
+pub fn handle_connection(stream: std::net::TcpStream, config: &RouterConfig, https: bool) {
     #[cfg(feature = "nightly")]
-    {
+    if https {
+        let mut stream = stream;
         let mut buf = [0; 5];
         let _ = stream.read(&mut buf);
         if buf[0] == 22 {
             if unsafe { SSL_CERTIFICATE.is_none() } {
                 todo!(); // TODO: add log and return
             }
-            //https
             let record = crate::https::tls::RecordMessage::new(buf.into());
             if let Ok(a) = record {
                 result_https_request(&stream, config, a)
             }
-        } else if buf == "GET /".as_bytes() {
-            // 因为读取 buf 时对原 Stream 进行了一次裁剪，所以在 get_request_str 函数中要把 "GET /" 加回去
-            //http
-            result_http_request(stream, config)
         }
+        return;
     }
-    #[cfg(not(feature = "nightly"))]
-    result_http_request(stream, config)
+    result_http_request(&stream, config, &mut 0, None)
 }
 
-fn result_http_request(mut stream: std::net::TcpStream, config: &RouterConfig) {
-    let buf_reader = std::io::BufReader::with_capacity(64, &mut stream);
+/// 为 TCP 流的读取设置一个总的截止时间，而不只是每一次读取的超时
+/// 这样逐字节缓慢发送请求的客户端 (slowloris) 也无法无限期的占用线程
+///
+/// timed_out: 是否因为超过截止时间而读取失败，它是共享的，以便在流被借用时也可以检查
+pub(super) struct DeadlineStream<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<std::time::Instant>,
+    timed_out: std::rc::Rc<std::cell::Cell<bool>>,
+}
+impl DeadlineStream<'_> {
+    /// 从现在开始计算截止时间，0 表示不限制
+    pub(super) fn set_timeout(&mut self, secs: u32) {
+        self.deadline = (secs != 0)
+            .then(|| std::time::Instant::now() + std::time::Duration::from_secs(secs.into()));
+        self.timed_out.set(false);
+    }
+}
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(std::time::Instant::now()) {
+                Some(a) if !a.is_zero() => Some(a),
+                _ => {
+                    self.timed_out.set(true);
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+            },
+            None => None,
+        };
+        if self.stream.set_read_timeout(timeout).is_err() {
+            log!(Debug, LOG[37]);
+        }
+        match self.stream.read(buf) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                self.timed_out.set(true);
+                Err(e)
+            }
+            a => a,
+        }
+    }
+}
 
-    let mut lines = std::io::BufRead::lines(buf_reader);
+/// 处理一个持久连接上已经到达的请求，供事件循环使用，参见 eventmode
+/// 与 handle_connection 不同，已经到达的请求都被处理完之后，它不会阻塞的等待下一个请求，而是返回 true ，
+/// 以便由事件循环等待这个连接再次可读，返回 false 时，这个连接应该被关闭
+/// served: 这个连接已经处理过的请求的数量，它会被更新
+pub fn serve_ready_requests(stream: &TcpStream, config: &RouterConfig, served: &mut u32) -> bool {
+    let mut parked = false;
+    result_http_request(stream, config, served, Some(&mut parked));
+    parked
+}
 
-    let req_str = get_request_str(&mut lines);
+/// 在同一个 TCP 流上循环处理请求，直到客户端或服务器决定关闭连接
+/// 读取缓冲区在整个连接中被复用，所以流水线 (pipelining) 中的后续请求不会丢失
+///
+/// park: 参见 serve_ready_requests ，此时只有在读取缓冲区为空时才会返回，所以流水线中的请求同样不会丢失
+fn result_http_request(
+    stream: &TcpStream,
+    config: &RouterConfig,
+    served_before: &mut u32,
+    mut park: Option<&mut bool>,
+) {
+    let enable_keep_alive = ENABLE_KEEP_ALIVE.load(Ordering::Relaxed);
+    let keep_alive_timeout = KEEP_ALIVE_TIMEOUT.load(Ordering::Relaxed);
+    let keep_alive_max = KEEP_ALIVE_MAX.load(Ordering::Relaxed);
+    let strict_parsing = STRICT_PARSING.load(Ordering::Relaxed);
+    let header_timeout = HEADER_TIMEOUT.load(Ordering::Relaxed);
+    let body_timeout = BODY_TIMEOUT.load(Ordering::Relaxed);
+    let max_body_size = match MAX_BODY_SIZE.load(Ordering::Relaxed) {
+        0 => u64::MAX,
+        a => a.into(),
+    };
 
-    if req_str.is_empty() {
-        if ENABLE_CODE_BAD_REQUEST.load(Ordering::Relaxed) {
-            let mut response = HttpResponse::new();
-            response.set_state("400 BAD REQUEST");
-            write_stream(stream, &mut response);
+    let timed_out = std::rc::Rc::new(std::cell::Cell::new(false));
+    let mut reader = std::io::BufReader::with_capacity(
+        1024,
+        DeadlineStream {
+            stream,
+            deadline: None,
+            timed_out: timed_out.clone(),
+        },
+    );
+    let mut served = *served_before;
+
+    loop {
+        if let Some(parked) = park.as_deref_mut() {
+            if served != *served_before && reader.buffer().is_empty() {
+                *served_before = served;
+                *parked = true;
+                return;
+            }
         }
-        return;
-    }
+        // 第一个请求之前，以及持久连接中的两个请求之间，客户端分别有 header-timeout 和 keep-alive-timeout 秒的时间开始发送请求
+        // 由事件循环唤醒时，请求已经开始到达，所以同样只有 header-timeout 秒
+        reader.get_mut().set_timeout(if served == *served_before {
+            header_timeout
+        } else {
+            keep_alive_timeout.max(1)
+        });
+        let req_str = match get_request_str(&mut reader, header_timeout) {
+            Ok(a) => a,
+            Err(None) => {
+                // 在持久连接中，没有请求意味着客户端关闭了连接或已经超时
+                if served == 0 && ENABLE_CODE_BAD_REQUEST.load(Ordering::Relaxed) {
+                    write_reject_response(stream, config, "400 BAD REQUEST", "", new_request_id());
+                }
+                return;
+            }
+            Err(Some(state)) => {
+                return write_reject_response(stream, config, state, "", new_request_id())
+            }
+        };
+        served += 1;
 
-    let mut request = if let Ok(req) = get_request(req_str) {
-        req
-    } else {
-        return;
-    };
+        if strict_parsing {
+            if let Err(e) = check_strict(&req_str) {
+                return write_strict_error(stream, config, e, "", new_request_id());
+            }
+        }
+        let mut request = if let Ok(req) = get_request(req_str) {
+            req
+        } else {
+            return;
+        };
+        let mut content_length = None;
+        if strict_parsing {
+            match request.check_framing() {
+                Ok(a) => content_length = a,
+                Err(e) => {
+                    return write_strict_error(
+                        stream,
+                        config,
+                        e,
+                        request.path(),
+                        request_id(&request),
+                    )
+                }
+            }
+        }
+
+        // 正在关闭时，持久连接在当前的请求完成之后就会被关闭
+        let mut keep_alive = enable_keep_alive
+            && served < keep_alive_max
+            && request.keep_alive()
+            && !super::shutdown::is_shutting_down();
 
-    if let Some(a) = request.get_header("Content-Length".to_owned()) {
-        request.set_content(Some(lines.take({
-            match a.parse() {
+        // 对 WebSocket 路由的握手会一直占用这个连接，其他请求则照常交给路由
+        #[cfg(not(feature = "no-glisp"))]
+        if let Some(script) = config.websocket.get(request.path()) {
+            if let Some(handshake) = crate::drop::websocket::check_handshake(&request).transpose() {
+                let accept = match handshake {
+                    Ok(a) => a,
+                    Err(state) => {
+                        let mut response =
+                            reject_response(config, state, request.path(), request_id(&request));
+                        // 告诉客户端服务器支持的版本，参见 RFC 6455 4.4
+                        response.set_header("Sec-WebSocket-Version", "13".to_owned());
+                        write_stream(stream, &mut response);
+                        return;
+                    }
+                };
+                let request_env = pipe_request_env(&request, None);
+                return super::websocket::serve(accept, &request_env, script, &mut reader, stream);
+            }
+        }
+
+        // 不支持分块传输编码的 HTTP/1.0 客户端只能通过关闭连接来得知流式主体的结束
+        let chunked = request.version() != "HTTP/1.0";
+
+        #[cfg(not(feature = "no-glisp"))]
+        if let Some(source) = config.sse.get(request.path()) {
+            if request.request_method() == "GET" {
+                let request_env = pipe_request_env(&request, None);
+                return super::sse::serve(
+                    source,
+                    &request_env,
+                    request.get_header("Last-Event-ID").cloned(),
+                    stream,
+                    chunked,
+                );
+            }
+        }
+
+        reader.get_mut().set_timeout(body_timeout);
+        if let Some(a) = request.get_header("Transfer-Encoding") {
+            // 同时存在 Content-Length 时，以 Transfer-Encoding 为准，但之后必须关闭连接，参见 RFC 9112 6.1
+            if request.get_header("Content-Length").is_some() {
+                keep_alive = false;
+            }
+            if a.rsplit(',')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("chunked")
+            {
+                request.set_content(Some(HttpContent::Chunked(
+                    ChunkedReader::with_limit(&mut reader, max_body_size).limit_lines(
+                        MAX_REQUEST_LINE.load(Ordering::Relaxed).into(),
+                        MAX_HEADERS.load(Ordering::Relaxed) as usize,
+                        MAX_HEADER_SIZE.load(Ordering::Relaxed).into(),
+                    ),
+                )))
+            } else {
+                keep_alive = false;
+            }
+        } else if let Some(a) = request.get_header("Content-Length") {
+            // 严格模式下，以 check_framing 规范化之后的长度为准
+            match content_length.or_else(|| a.parse().ok()) {
+                Some(length) if length > max_body_size => {
+                    return write_reject_response(
+                        stream,
+                        config,
+                        "413 CONTENT TOO LARGE",
+                        request.path(),
+                        request_id(&request),
+                    )
+                }
+                Some(length) => request.set_content(Some(HttpContent::Length(
+                    std::io::Read::take(&mut reader, length),
+                ))),
+                // 无法确定主体的长度，也就无法找到下一个请求的开头
+                None => keep_alive = false,
+            }
+        }
+
+        let response = &mut HttpResponse::new();
+        response
+            .set_default_headers("Tiny-Tiny-Web/2")
+            .result_timeerr_default();
+        if !crate::router::router(&mut request, response, config) {
+            return;
+        }
+        let enable_pipe = crate::config::ENABLE_PIPE.load(Ordering::Relaxed);
+        // 表单只有 Pipe 会用到，上传的临时文件会在 form 被销毁，即这个请求结束时被删除
+        #[cfg(not(feature = "no-glisp"))]
+        let form = if enable_pipe {
+            match request.content_form("temp") {
                 Ok(a) => a,
-                _ => 0,
+                Err(_) if request.content_too_large() => {
+                    return write_reject_response(
+                        stream,
+                        config,
+                        "413 CONTENT TOO LARGE",
+                        request.path(),
+                        request_id(&request),
+                    )
+                }
+                Err(_) if timed_out.get() => {
+                    return write_reject_response(
+                        stream,
+                        config,
+                        "408 REQUEST TIMEOUT",
+                        request.path(),
+                        request_id(&request),
+                    )
+                }
+                Err(_) => {
+                    return write_reject_response(
+                        stream,
+                        config,
+                        "400 BAD REQUEST",
+                        request.path(),
+                        request_id(&request),
+                    )
+                }
             }
-        })))
+        } else {
+            None
+        };
+        if !request.discard_content() {
+            if request.content_too_large() {
+                return write_reject_response(
+                    stream,
+                    config,
+                    "413 CONTENT TOO LARGE",
+                    request.path(),
+                    request_id(&request),
+                );
+            }
+            if timed_out.get() {
+                return write_reject_response(
+                    stream,
+                    config,
+                    "408 REQUEST TIMEOUT",
+                    request.path(),
+                    request_id(&request),
+                );
+            }
+            keep_alive = false;
+        }
+        if response.content_stream().is_some()
+            && !chunked
+            && response.remove_header("Transfer-Encoding").is_some()
+        {
+            keep_alive = false;
+        }
+        set_connection_headers(
+            response,
+            keep_alive,
+            keep_alive_timeout,
+            keep_alive_max.saturating_sub(served),
+        );
+
+        let enable_debug = crate::config::ENABLE_DEBUG.load(Ordering::Relaxed);
+        if enable_debug {
+            let content_stream = response.get_stream();
+            match std::str::from_utf8(&content_stream) {
+                Ok(v) => {
+                    if !enable_pipe {
+                        log!(Debug, format!("{}{}\n", LOG[8], v))
+                    }
+                }
+                Err(_) => log!(Debug, format!("{}{:?}\n", LOG[8], content_stream)),
+            }
+        }
+        #[cfg(not(feature = "no-glisp"))]
+        if enable_pipe {
+            if let Some(content) = response.content_unref() {
+                if let Ok(a) = std::str::from_utf8(&content) {
+                    let request_env = pipe_request_env(&request, form.as_ref());
+                    if pipe(
+                        config,
+                        a,
+                        &request_env,
+                        enable_debug,
+                        response,
+                        // HEAD 请求不能有主体，所以 Pipe 不能通过 send 函数流式输出
+                        (request.request_method() != "HEAD").then_some(stream),
+                        chunked,
+                    ) {
+                        // Pipe 已经通过 send 函数流式的写出了整个响应
+                        if !chunked || !keep_alive {
+                            return;
+                        }
+                        continue;
+                    }
+                }
+            }
+        }
+
+        compress_response(&request, response, config);
+
+        if request.request_method() == "HEAD" {
+            response.remove_content();
+        }
+
+        if !write_stream(stream, response) || !keep_alive {
+            return;
+        }
     }
+}
 
-    let response = &mut HttpResponse::new();
-    response
-        .set_default_headers("Tiny-Tiny-Web/2")
-        .result_timeerr_default();
-    if !crate::router::router(request, response, config) {
+/// 按照 `Accept-Encoding` 压缩响应主体，这发生在 Pipe 之后，所以被压缩的总是最终的内容
+/// 流式的主体、部分内容和已经被编码的内容不会被压缩
+fn compress_response<T>(
+    request: &HttpRequest<T>,
+    response: &mut HttpResponse,
+    config: &RouterConfig,
+) {
+    if config.compress_types.is_empty()
+        || !response.state().starts_with("200")
+        || response.get_header("Content-Encoding").is_some()
+    {
+        return;
+    }
+    let content_type = match response.get_header("Content-Type") {
+        Some(a) => a
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase(),
+        None => return,
+    };
+    if !config.compress_types.contains(&content_type) {
         return;
     }
+    match response.content_ref() {
+        Some(a) if a.len() >= COMPRESS_MIN_SIZE.load(Ordering::Relaxed) as usize => (),
+        _ => return,
+    }
+    // 无论是否压缩，缓存都需要知道响应会随 Accept-Encoding 而变化
+    response.set_header("Vary", "Accept-Encoding".to_owned());
 
-    let enable_pipe = crate::config::ENABLE_PIPE.load(Ordering::Relaxed);
-    let enable_debug = crate::config::ENABLE_DEBUG.load(Ordering::Relaxed);
-    if enable_debug {
-        let content_stream = response.get_stream();
-        match std::str::from_utf8(&content_stream) {
-            Ok(v) => {
-                if !enable_pipe {
-                    log!(Debug, format!("{}{}\n", LOG[8], v))
-                }
-            }
-            Err(_) => log!(Debug, format!("{}{:?}\n", LOG[8], content_stream)),
-        }
+    let encoding = match request
+        .get_header("Accept-Encoding")
+        .and_then(|a| negotiate_encoding(a, &["gzip", "deflate"]))
+    {
+        Some(a) => a,
+        None => return,
+    };
+    let content = response.content_ref().as_deref().unwrap_or_default();
+    let compressed = match encoding {
+        "gzip" => deflate::gzip(content),
+        _ => deflate::zlib(content),
+    };
+    if compressed.len() >= content.len() {
+        return;
     }
-    #[cfg(not(feature = "no-glisp"))]
-    if enable_pipe {
-        if let Some(content) = response.content_unref() {
-            if let Ok(a) = std::str::from_utf8(&content) {
-                pipe(config, a, enable_debug, response)
-            }
+    // 压缩后的内容与原文件不再逐字节相同，所以只能使用弱验证器
+    if let Some(etag) = response.get_header("ETag") {
+        if etag.starts_with('"') {
+            let etag = "W/".to_owned() + etag;
+            response.set_header("ETag", etag);
         }
     }
+    response.set_header("Content-Encoding", encoding.to_owned());
+    response.set_header("Content-Length", compressed.len().to_string());
+    response.set_content(compressed);
+}
+
+/// 请求超出了限制或格式错误时，返回一个只有错误页面的响应，并关闭连接
+/// 这时请求可能还没有被完整的读取，所以连接不能被复用
+fn write_reject_response(
+    stream: &TcpStream,
+    config: &RouterConfig,
+    state: &str,
+    path: &str,
+    request_id: String,
+) {
+    write_stream(
+        stream,
+        &mut reject_response(config, state, path, request_id),
+    );
+}
+
+/// 所有的线程都在忙，并且等待的连接已经填满了队列时，新的连接会在接受它的线程中立即被拒绝
+/// 它的请求不会被读取，写出的时间也有一个很短的上限，以免拖慢接受连接的循环
+pub fn write_busy_response(stream: &TcpStream, config: &RouterConfig) {
+    let _ = stream.set_write_timeout(Some(std::time::Duration::from_secs(1)));
+    let mut response = reject_response(config, "503 SERVICE UNAVAILABLE", "", new_request_id());
+    response.set_header("Retry-After", "1".to_owned());
+    write_stream(stream, &mut response);
+}
+
+/// 收到 SIGUSR1 时，在日志中输出线程池中每一个线程的统计信息
+pub fn stats_init(threadpool: &ThreadPool) {
+    signal::listen(SIGUSR1);
+    let threadpool = threadpool.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if !signal::take(SIGUSR1) {
+            continue;
+        }
+        log!(Info, format!("{}{}", LOG[50], threadpool.queued()));
+        for (i, a) in threadpool.stats().iter().enumerate() {
+            log!(
+                Info,
+                format!(
+                    "{}{} {} {} {}",
+                    LOG[51],
+                    i,
+                    a.busy,
+                    a.handled,
+                    a.busy_time.as_millis()
+                )
+            );
+        }
+    });
+}
+
+/// path 和 request_id 会被填入错误页面，参见 router::set_error_page ，还没有解析出请求时，path 为空
+fn reject_response(
+    config: &RouterConfig,
+    state: &str,
+    path: &str,
+    request_id: String,
+) -> HttpResponse {
+    log!(Debug, format!("{}{}", LOG[38], state));
+    let mut response = HttpResponse::new();
+    response
+        .set_default_headers("Tiny-Tiny-Web/2")
+        .result_timeerr_default();
+    response.set_version("HTTP/1.1");
+    response.set_state(state);
+    response.set_header("Connection", "close".to_owned());
+    set_error_page(&mut response, config, path, &request_id);
+    response
+}
+
+fn write_strict_error(
+    stream: &TcpStream,
+    config: &RouterConfig,
+    e: HttpStrictError,
+    path: &str,
+    request_id: String,
+) {
+    log!(Debug, format!("{}{}", LOG[39], e.reason));
+    write_reject_response(stream, config, e.state, path, request_id)
+}
 
-    write_stream(stream, response)
+fn set_connection_headers(response: &mut HttpResponse, keep_alive: bool, timeout: u32, max: u32) {
+    if keep_alive {
+        response.set_header("Connection", "keep-alive".to_owned());
+        response.set_header("Keep-Alive", format!("timeout={}, max={}", timeout, max));
+    } else {
+        response.set_header("Connection", "close".to_owned());
+    }
 }
 
 fn get_random_32bytes() -> [u8; 32] {
@@ -328,7 +783,7 @@ fn result_https_request(
     }
 }
 
-fn get_request<'a>(req_str: String) -> Result<HttpRequest<'a, TcpStream>, ()> {
+fn get_request<'a, T>(req_str: String) -> Result<HttpRequest<'a, T>, ()> {
     if crate::config::ENABLE_DEBUG.load(Ordering::Relaxed) {
         match HttpRequest::from_string(req_str.clone()) {
             Ok(req) => {
@@ -348,42 +803,253 @@ fn get_request<'a>(req_str: String) -> Result<HttpRequest<'a, TcpStream>, ()> {
     }
 }
 
-fn get_request_str(lines: &mut std::io::Lines<std::io::BufReader<&mut TcpStream>>) -> String {
+/// 读取请求行与请求头，直到遇到空行
+/// 请求行之前的空行会被忽略，参见 RFC 9112 2.2
+///
+/// 一旦收到了请求的第一个字节，剩下的请求行与请求头必须在 header_timeout 秒内被读取完毕
+/// 如果连接在发送请求之前就已关闭或超时，返回 Err(None)
+/// 如果请求超出了限制，或者在发送的过程中超时，返回 Err(应该返回的状态)
+fn get_request_str(
+    reader: &mut std::io::BufReader<DeadlineStream>,
+    header_timeout: u32,
+) -> Result<String, Option<&'static str>> {
+    match std::io::BufRead::fill_buf(reader) {
+        Ok(a) if !a.is_empty() => reader.get_mut().set_timeout(header_timeout),
+        _ => return Err(None),
+    }
+    let max_request_line = MAX_REQUEST_LINE.load(Ordering::Relaxed) as u64;
+    let max_headers = MAX_HEADERS.load(Ordering::Relaxed) as usize;
+    let max_header_size = MAX_HEADER_SIZE.load(Ordering::Relaxed) as u64;
+
     let mut str = String::new();
-    #[allow(clippy::never_loop)]
+    let mut headers = 0;
+    let mut header_size = 0;
     loop {
-        let line = lines.next();
-        match line {
-            Some(Ok(a)) => {
-                if a.is_empty() {
+        // 请求行和请求头分别有各自的长度限制，读取到限制之外的一个字节就可以知道是否超出了限制
+        // 结束请求头的空行不计入限制，所以额外允许两个字节，请求头在分类之后再检查
+        let limit = if str.is_empty() {
+            max_request_line
+        } else {
+            max_header_size - header_size + 2
+        };
+        let mut line = String::new();
+        match std::io::BufRead::read_line(
+            &mut std::io::Read::take(&mut *reader, limit + 1),
+            &mut line,
+        ) {
+            Ok(0) => return Err(None),
+            Ok(n) => {
+                if n as u64 > limit {
+                    return Err(Some(if str.is_empty() {
+                        "414 URI TOO LONG"
+                    } else {
+                        "431 REQUEST HEADER FIELDS TOO LARGE"
+                    }));
+                }
+                let line = line.trim_end_matches(['\r', '\n']);
+                if line.is_empty() {
+                    if str.is_empty() {
+                        continue;
+                    }
                     break;
-                };
-                str += &(a + "\r\n")
+                }
+                if !str.is_empty() {
+                    headers += 1;
+                    header_size += n as u64;
+                    if headers > max_headers || header_size > max_header_size {
+                        return Err(Some("431 REQUEST HEADER FIELDS TOO LARGE"));
+                    }
+                }
+                str += line;
+                str += "\r\n";
             }
-            _ => break,
+            Err(_) if reader.get_ref().timed_out.get() => return Err(Some("408 REQUEST TIMEOUT")),
+            Err(_) => return Err(None),
+        }
+    }
+    Ok(str)
+}
+
+/// 返回值说明了是否写入成功
+fn write_stream(mut stream: &TcpStream, response: &mut HttpResponse) -> bool {
+    if response.write_to(&mut stream).is_err() {
+        log!(Debug, LOG[6]);
+        return false;
+    }
+    true
+}
+
+/// Pipe 通过 send 函数流式输出时使用的输出流
+/// 在第一次写入时才会写出响应头，所以没有使用 send 的 Pipe 不受影响
+/// 在被丢弃时，会写出分块传输编码的最后一个块
+#[cfg(not(feature = "no-glisp"))]
+struct PipeOutput {
+    stream: TcpStream,
+    head: Option<HttpResponse>,
+    chunked: bool,
+    started: std::rc::Rc<std::cell::Cell<bool>>,
+}
+#[cfg(not(feature = "no-glisp"))]
+impl std::io::Write for PipeOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(mut head) = self.head.take() {
+            // 在第一次调用 send 之前由 Pipe 设置的响应头，例如 Set-Cookie
+            for (k, v) in crate::glisp::core::take_headers() {
+                head.append_header(&k, v);
+            }
+            self.started.set(true);
+            self.stream.write_all(&head.get_head_stream())?;
+        }
+        if self.chunked {
+            crate::drop::http::ChunkedWriter::new(&mut self.stream).write(buf)
+        } else {
+            self.stream.write(buf)
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+#[cfg(not(feature = "no-glisp"))]
+impl Drop for PipeOutput {
+    fn drop(&mut self) {
+        if self.head.is_none()
+            && self.chunked
+            && crate::drop::http::ChunkedWriter::new(&mut self.stream)
+                .finish()
+                .is_err()
+        {
+            log!(Debug, LOG[6])
         }
-        break;
     }
-    #[cfg(feature = "nightly")]
-    return "GET /".to_owned() + &str;
-    #[cfg(not(feature = "nightly"))]
-    return str;
 }
 
-fn write_stream(mut stream: TcpStream, response: &mut HttpResponse) {
-    if std::io::Write::write_all(&mut stream, &response.get_stream()).is_err() {
-        log!(Debug, LOG[6])
+/// Pipe 中可以使用的，关于当前请求的变量
+/// PATH: 被解码和规范化的路径
+/// QUERY: 查询字符串，形如 `(("a" "1") ("b" "2"))` ，可以使用 assoc 函数来取值
+/// FORM: 表单中的字段，格式与 QUERY 相同
+/// COOKIES: 请求中的 Cookie ，格式与 QUERY 相同
+/// FILES: 表单中上传的文件，形如 `(("字段名" "临时文件路径" "文件名" "Content-Type" 大小))`
+#[cfg(not(feature = "no-glisp"))]
+pub(super) fn pipe_request_env<T>(
+    request: &HttpRequest<T>,
+    form: Option<&crate::drop::form::Form>,
+) -> Vec<(&'static str, crate::glisp::core::Expression)> {
+    use crate::glisp::core::Expression;
+    let pairs = |a: &[(String, String)]| {
+        Expression::List(
+            a.iter()
+                .map(|(k, v)| {
+                    Expression::List(vec![
+                        Expression::String(k.to_owned()),
+                        Expression::String(v.to_owned()),
+                    ])
+                })
+                .collect(),
+        )
+    };
+    vec![
+        ("PATH", Expression::String(request.path().to_owned())),
+        ("QUERY", pairs(request.query())),
+        ("FORM", pairs(form.map_or(&[], |a| &a.fields))),
+        ("COOKIES", pairs(&request.cookies())),
+        (
+            "FILES",
+            Expression::List(
+                form.map_or(&[][..], |a| &a.files)
+                    .iter()
+                    .map(|a| {
+                        Expression::List(vec![
+                            Expression::String(a.name.to_owned()),
+                            Expression::String(a.path.to_owned()),
+                            Expression::String(a.filename.to_owned()),
+                            Expression::String(a.content_type.to_owned()),
+                            Expression::Number(a.size as f64),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+    ]
+}
+
+/// 返回值说明了 Pipe 是否已经通过 send 函数流式的写出了整个响应
+/// 一旦某个 Pipe 开始了流式输出，之后的 Pipe 都不会再被执行
+#[cfg(not(feature = "no-glisp"))]
+fn pipe(
+    config: &RouterConfig,
+    content: &str,
+    request_env: &[(&str, crate::glisp::core::Expression)],
+    enable_debug: bool,
+    response: &mut HttpResponse,
+    stream: Option<&TcpStream>,
+    chunked: bool,
+) -> bool {
+    let started = std::rc::Rc::new(std::cell::Cell::new(false));
+    // 丢弃在 Pipe 之外设置的响应头
+    crate::glisp::core::take_headers();
+    if let Some(Ok(stream)) = stream.map(|a| a.try_clone()) {
+        let mut head = response.clone();
+        head.set_content_stream(
+            std::sync::Arc::new(std::sync::Mutex::new(std::io::empty())),
+            None,
+        );
+        if !chunked {
+            head.remove_header("Transfer-Encoding");
+            head.set_header("Connection", "close".to_owned());
+        }
+        crate::glisp::core::set_output(Some(Box::new(PipeOutput {
+            stream,
+            head: Some(head),
+            chunked,
+            started: started.clone(),
+        })));
     }
+    pipe_eval(
+        config,
+        content,
+        request_env,
+        enable_debug,
+        response,
+        &started,
+    );
+    crate::glisp::core::set_output(None);
+    started.get()
 }
 
-fn pipe(config: &RouterConfig, content: &str, enable_debug: bool, response: &mut HttpResponse) {
+#[cfg(not(feature = "no-glisp"))]
+fn pipe_eval(
+    config: &RouterConfig,
+    content: &str,
+    request_env: &[(&str, crate::glisp::core::Expression)],
+    enable_debug: bool,
+    response: &mut HttpResponse,
+    started: &std::cell::Cell<bool>,
+) {
     for e in &config.pipe {
         let env = &mut crate::glisp::core::default_env();
         env.data.insert(
             "CONTENT".to_owned(),
             crate::glisp::core::Expression::String(content.to_owned()),
         );
-        match crate::glisp::core::parse_eval(e.to_string(), env, None) {
+        for (k, v) in request_env {
+            env.data.insert(k.to_string(), v.clone());
+        }
+        let result = crate::glisp::core::parse_eval(e.to_string(), env, None);
+        // 出错的 Pipe 设置的响应头会被丢弃
+        let headers = crate::glisp::core::take_headers();
+        if started.get() {
+            return;
+        }
+        if matches!(
+            result,
+            Ok(crate::glisp::core::Expression::String(_) | crate::glisp::core::Expression::Bool(_))
+        ) {
+            for (k, v) in headers {
+                response.append_header(&k, v);
+            }
+        }
+        match result {
             Ok(crate::glisp::core::Expression::String(res)) => {
                 if enable_debug {
                     log!(Debug, format!("{}{}\n", LOG[8], res));
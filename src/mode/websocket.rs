@@ -0,0 +1,177 @@
+/* Tiny Tiny Web
+ * Copyright (C) 2024 Plasma (https://github.com/duoduo70/Tiny-Tiny-Web/).
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program;
+ * if not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! WebSocket 连接的处理
+//! 握手成功后，这个连接会被一直占用，客户端发来的每一条消息都会交给 `@ws` 绑定的 Glisp 脚本
+
+use std::{io::Write, net::TcpStream, sync::atomic::Ordering};
+
+use super::utils::DeadlineStream;
+use crate::{
+    config::{MAX_BODY_SIZE, WEBSOCKET_TIMEOUT},
+    drop::{
+        http::HttpResponse,
+        log::LogLevel::*,
+        websocket::{is_valid_close_code, read_frame, write_close, write_frame, Opcode},
+    },
+    glisp::core::Expression,
+    i18n::LOG,
+    macros::*,
+    utils::TimeErr,
+};
+
+/// accept: 握手响应中的 `Sec-WebSocket-Accept` ，参见 drop::websocket::check_handshake
+/// request_env: 与 Pipe 相同的，关于握手请求的变量，参见 utils::pipe_request_env
+pub(super) fn serve(
+    accept: String,
+    request_env: &[(&str, Expression)],
+    script: &str,
+    reader: &mut std::io::BufReader<DeadlineStream>,
+    mut stream: &TcpStream,
+) {
+    let mut response = HttpResponse::new();
+    response
+        .set_default_headers("Tiny-Tiny-Web/2")
+        .result_timeerr_default();
+    response.set_version("HTTP/1.1");
+    response.set_state("101 SWITCHING PROTOCOLS");
+    response.set_header("Upgrade", "websocket".to_owned());
+    response.set_header("Connection", "Upgrade".to_owned());
+    response.set_header("Sec-WebSocket-Accept", accept);
+    if stream.write_all(&response.get_head_stream()).is_err() {
+        return;
+    }
+    log!(Debug, LOG[40]);
+
+    if let Ok(output) = stream.try_clone() {
+        crate::glisp::core::set_output(Some(Box::new(WebSocketOutput(output))));
+    }
+    serve_messages(request_env, script, reader, stream);
+    crate::glisp::core::set_output(None);
+}
+
+fn serve_messages(
+    request_env: &[(&str, Expression)],
+    script: &str,
+    reader: &mut std::io::BufReader<DeadlineStream>,
+    mut stream: &TcpStream,
+) {
+    let timeout = WEBSOCKET_TIMEOUT.load(Ordering::Relaxed);
+    let max_message_size = match MAX_BODY_SIZE.load(Ordering::Relaxed) {
+        0 => u64::MAX,
+        a => a.into(),
+    };
+    // 正在接收的被分片的消息
+    let mut message: Option<(Opcode, Vec<u8>)> = None;
+
+    loop {
+        reader.get_mut().set_timeout(timeout);
+        let received = message.as_ref().map_or(0, |(_, a)| a.len() as u64);
+        let frame = match read_frame(reader, max_message_size - received) {
+            Ok(a) => a,
+            Err(e) => {
+                if let Some(code) = e.close_code() {
+                    let _ = write_close(&mut stream, code);
+                }
+                return;
+            }
+        };
+        match frame.opcode {
+            Opcode::Ping => {
+                if write_frame(&mut stream, Opcode::Pong, &frame.payload).is_err() {
+                    return;
+                }
+                continue;
+            }
+            Opcode::Pong => continue,
+            Opcode::Close => {
+                // 回应客户端的关闭帧，然后关闭连接，参见 RFC 6455 5.5.1
+                // 不能被发送的状态码意味着客户端违反了协议
+                let code = match frame.payload[..] {
+                    [] => 1000,
+                    [a, b, ..] if is_valid_close_code(u16::from_be_bytes([a, b])) => {
+                        u16::from_be_bytes([a, b])
+                    }
+                    _ => 1002,
+                };
+                let _ = write_close(&mut stream, code);
+                return;
+            }
+            Opcode::Continuation => match &mut message {
+                Some((_, a)) => a.extend_from_slice(&frame.payload),
+                None => {
+                    let _ = write_close(&mut stream, 1002);
+                    return;
+                }
+            },
+            // 上一条消息还没有结束时，不能开始新的消息
+            _ if message.is_some() => {
+                let _ = write_close(&mut stream, 1002);
+                return;
+            }
+            opcode => message = Some((opcode, frame.payload)),
+        }
+        if !frame.fin {
+            continue;
+        }
+
+        let (opcode, payload) = message.take().unwrap_or((Opcode::Binary, vec![]));
+        let text = match String::from_utf8(payload) {
+            Ok(a) => a,
+            // 文本消息必须是合法的 UTF-8 ，二进制消息则尽力的转换为字符串
+            Err(_) if opcode == Opcode::Text => {
+                let _ = write_close(&mut stream, 1007);
+                return;
+            }
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        };
+        if let Some(reply) = eval_message(request_env, script, text, opcode == Opcode::Binary) {
+            if write_frame(&mut stream, Opcode::Text, reply.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 用一条消息执行脚本，如果脚本返回了字符串，则它会作为回复被发送
+fn eval_message(
+    request_env: &[(&str, Expression)],
+    script: &str,
+    message: String,
+    binary: bool,
+) -> Option<String> {
+    let env = &mut crate::glisp::core::default_env();
+    env.data
+        .insert("MESSAGE".to_owned(), Expression::String(message));
+    env.data
+        .insert("BINARY".to_owned(), Expression::Bool(binary));
+    for (k, v) in request_env {
+        env.data.insert(k.to_string(), v.clone());
+    }
+    match crate::glisp::core::parse_eval(script.to_owned(), env, None) {
+        Ok(Expression::String(res)) => return Some(res),
+        Ok(Expression::Bool(_)) => (),
+        Ok(a) => log!(Error, format!("[{}] {} {}", LOG[32], LOG[35], a)),
+        Err(crate::glisp::core::GError::Reason(msg)) => {
+            log!(Info, format!("[{}] {} {}", LOG[32], LOG[34], msg))
+        }
+    }
+    None
+}
+
+/// 脚本通过 send 函数发送的每一个字符串都是一个文本消息
+struct WebSocketOutput(TcpStream);
+impl Write for WebSocketOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        write_frame(&mut self.0, Opcode::Text, buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
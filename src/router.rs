@@ -5,8 +5,20 @@
  * along with this program;
  * if not, see <https://www.gnu.org/licenses/>.
  */
-use crate::{config::*, drop::http::*, drop::log::LogLevel::*, i18n::LOG, macros::*};
-use std::sync::atomic::Ordering;
+use crate::{
+    config::*,
+    drop::http::*,
+    drop::log::LogLevel::*,
+    drop::lru::LruCache,
+    drop::time::{parse_http_date, Time},
+    i18n::LOG,
+    macros::*,
+    utils::TimeErr,
+};
+use std::{
+    io::{Read, Seek},
+    sync::{atomic::Ordering, Arc, Mutex},
+};
 
 /// 这是一个回调函数，返回值说明了本函数是否修改了 `res`
 /// 如果请求不符合任何规则，则该函数返回 false
@@ -17,115 +29,716 @@ use std::sync::atomic::Ordering;
 ///
 /// TODO: 该函数应该被 config 尽量的惰性构造来加快运行速度
 /// TODO: 需要注释或重构
-pub fn router<'a>(
-    req: HttpRequest<std::net::TcpStream>,
+pub fn router<'a, T>(
+    req: &mut HttpRequest<T>,
     res: &'a mut HttpResponse,
     config: &'a RouterConfig,
 ) -> bool {
+    // `OPTIONS *` 不指向任何路由，参见 RFC 9110 9.3.7
+    if req.request_method() == "OPTIONS" && req.url() == "*" {
+        return router_iftype_options_server(res, config);
+    }
     let serve_args = &config.serve_files_info;
-    if !serve_args.contains_key(&req.url().to_owned()) {
-        return router_iftype_err(res, config);
-    };
-
-    res.set_header(
-        "Content-Type",
-        config
-            .serve_files_info
-            .get(&req.url().to_owned())
-            .unwrap()
-            .content_type
-            .clone(),
-    );
-    let str = if let Some(content) = get_response_content(&req, config) {
-        content
+    let dir_info;
+    let info = if let Some(info) = serve_args.get(&req.path().to_owned()) {
+        info
     } else {
-        return false;
+        match router_iftype_dir(req, res, config) {
+            Ok(a) => {
+                dir_info = a;
+                &dir_info
+            }
+            Err(ret) => return ret,
+        }
     };
+    if req.request_method() == "OPTIONS" {
+        return router_iftype_options(res, info);
+    }
+    if !info.methods.contains(req.request_method()) {
+        return router_iftype_method_not_allowed(req, res, config, info);
+    }
 
-    if let Some(k) = serve_args.get(&req.url().to_owned()) {
-        if let Some(replaces) = &k.replace {
-            return router_iftype_replace(
-                req,
-                res,
-                config,
-                replaces,
-                match std::str::from_utf8(&str) {
-                    Ok(v) => v.to_owned(),
-                    Err(_) => {
-                        log!(Debug, LOG[31]);
-                        return false;
-                    }
-                },
-            );
+    res.set_header("Content-Type", info.content_type.clone());
+
+    let mut path = "export".to_owned() + &info.file_path;
+    if !is_contained(config, &path) {
+        return router_iftype_forbidden(req, res, config, &path);
+    }
+
+    // 只有原样发送的文件才支持预压缩文件、条件请求和范围请求
+    // 被 inject 替换或经过 Pipe 的内容是无法预先得知其长度的，也无法由文件本身判断它是否被修改
+    if info.replace.is_none() && !ENABLE_PIPE.load(Ordering::Relaxed) {
+        let mut encoding = None;
+        if info.precompressed {
+            res.set_header("Vary", "Accept-Encoding".to_owned());
+            if let Some((a, ext)) = get_precompressed(req, config, &path) {
+                res.set_header("Content-Encoding", a.to_owned());
+                path += ext;
+                encoding = Some(a);
+            }
+        }
+        res.set_header("Accept-Ranges", "bytes".to_owned());
+        let file = get_file(&path).ok();
+        let validators = file.as_ref().and_then(|a| get_validators(a, encoding));
+        if let Some((etag, mtime)) = &validators {
+            res.set_header("ETag", etag.clone());
+            if let Some(date) = file.as_ref().and_then(|a| a.last_modified.clone()) {
+                res.set_header("Last-Modified", date);
+            }
+            if matches!(req.request_method().as_str(), "GET" | "HEAD")
+                && is_not_modified(req, etag, *mtime)
+            {
+                return router_iftype_not_modified(res, &path);
+            }
+        }
+        if req.request_method() == "GET" {
+            if let Some(range) = req.get_header("Range") {
+                if let Some(ret) =
+                    router_iftype_range(req, res, config, info, &path, range, validators.as_ref())
+                {
+                    return ret;
+                }
+            }
+        }
+    }
+
+    if let Some((stream, len)) = get_response_content_stream(info, &path) {
+        res.set_version("HTTP/1.1");
+        res.set_state("200 OK");
+        res.set_content_stream(stream, Some(len));
+        log!(Debug, format!("{}{}", LOG[14], path));
+        return true;
+    }
+
+    let str = match get_response_content(&path) {
+        Ok(a) => a,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            reset_response(res);
+            return router_iftype_err(req, res, config);
         }
+        Err(_) => {
+            log!(Error, format!("{}{}", LOG[22], path));
+            return router_iftype_internal_error(req, res, config);
+        }
+    };
+
+    if let Some(replaces) = &info.replace {
+        return router_iftype_replace(
+            res,
+            info,
+            replaces,
+            match std::str::from_utf8(&str) {
+                Ok(v) => v.to_owned(),
+                Err(_) => {
+                    log!(Error, format!("{}{}", LOG[31], path));
+                    return router_iftype_internal_error(req, res, config);
+                }
+            },
+        );
     }
 
     res.set_version("HTTP/1.1");
     res.set_state("200 OK");
     res.set_header("Content-Length", str.len().to_string());
     res.set_content(str);
-    log!(
-        Debug,
-        format!("{}{}", LOG[14], "export".to_owned() + req.url())
+    log!(Debug, format!("{}{}", LOG[14], path));
+
+    true
+}
+
+/// 在被挂载的目录中查找请求的路径，URL 前缀最长的目录优先
+/// 如果找到了一个文件，返回 Ok(它的元数据) ，之后它会像其它被托管的文件一样被发送
+/// 否则返回 Err(与 router 相同的返回值) ，这时 res 已经被设置，例如重定向或目录列表
+///
+/// 以 `.` 开头的文件和目录是隐藏的，例如 `.git` ，它们不会被发送，也不会被列出
+fn router_iftype_dir<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+) -> Result<ServeFileData, bool> {
+    let segments = req.segments();
+    let dir = config
+        .serve_dirs
+        .iter()
+        .filter(|a| segments.starts_with(&a.url_segments))
+        .max_by_key(|a| a.url_segments.len());
+    let dir = match dir {
+        Some(a) => a,
+        None => return Err(router_iftype_err(req, res, config)),
+    };
+    let rest = &segments[dir.url_segments.len()..];
+    // 被解码的 `%2F` 和 `%5C` 可能使一个路径段包含分隔符
+    if rest
+        .iter()
+        .any(|a| a.starts_with('.') || a.contains(['/', '\\', '\0']))
+    {
+        return Err(router_iftype_err(req, res, config));
+    }
+    let mut file_path = dir.dir_path.clone();
+    for a in rest {
+        file_path += "/";
+        file_path += a;
+    }
+
+    let path = std::path::Path::new("export").join(file_path.trim_start_matches('/'));
+    if !is_contained(config, &path.to_string_lossy()) {
+        return Err(router_iftype_forbidden(
+            req,
+            res,
+            config,
+            &path.to_string_lossy(),
+        ));
+    }
+    if path.is_dir() {
+        // 目录的 URL 必须以 `/` 结尾，否则页面中的相对链接会指向上一级目录
+        if !req.path().ends_with('/') && !segments.is_empty() {
+            return Err(router_iftype_redirect(req, res, segments));
+        }
+        if path.join("index.html").is_file() {
+            file_path += "/index.html";
+        } else if dir.autoindex {
+            return Err(router_iftype_autoindex(
+                req,
+                res,
+                config,
+                &path,
+                rest.is_empty(),
+            ));
+        } else {
+            res.set_version("HTTP/1.1");
+            res.set_state("403 FORBIDDEN");
+            set_error_page(res, config, req.path(), &request_id(req));
+            return Err(true);
+        }
+    } else if !path.is_file() {
+        return Err(router_iftype_err(req, res, config));
+    }
+
+    let mut info = ServeFileData::from_with_content_type(
+        file_path.clone(),
+        ServeFileData::auto_content_type(&file_path, &config.mime_bind),
     );
+    info.precompressed = dir.precompressed;
+    Ok(info)
+}
 
+/// 将缺少结尾的 `/` 的目录 URL 重定向到以 `/` 结尾的 URL ，查询字符串会被保留
+fn router_iftype_redirect<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    segments: &[String],
+) -> bool {
+    let mut location = String::new();
+    for a in segments {
+        location += "/";
+        location += &crate::drop::url::percent_encode(a);
+    }
+    location += "/";
+    if let Some((_, query)) = req.url().split_once('?') {
+        location += "?";
+        location += query.split('#').next().unwrap_or_default();
+    }
+    res.set_version("HTTP/1.1");
+    res.set_state("301 MOVED PERMANENTLY");
+    res.set_header("Location", location);
+    res.set_header("Content-Length", "0".to_owned());
+    res.set_content(vec![]);
     true
 }
 
-fn get_response_content<'a>(
-    req: &'a HttpRequest<std::net::TcpStream>,
-    config: &'a RouterConfig,
-) -> Option<Vec<u8>> {
-    let _stream = std::fs::read(
-        "export".to_owned()
-            + &config
-                .serve_files_info
-                .get(&req.url().to_owned())
-                .unwrap()
-                .file_path,
+/// 生成一个列出目录内容的页面，包含每一项的大小和修改时间，目录排在文件之前
+/// is_root: 是否是被挂载的目录本身，它没有指向上一级目录的链接
+fn router_iftype_autoindex<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+    path: &std::path::Path,
+    is_root: bool,
+) -> bool {
+    if !matches!(req.request_method().as_str(), "GET" | "HEAD") {
+        res.set_version("HTTP/1.1");
+        res.set_state("405 METHOD NOT ALLOWED");
+        res.set_header("Allow", "GET, HEAD, OPTIONS".to_owned());
+        set_error_page(res, config, req.path(), &request_id(req));
+        return true;
+    }
+    let mut entries: Vec<(bool, String, u64, u64)> = vec![];
+    if let Ok(dir) = std::fs::read_dir(path) {
+        for entry in dir.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let metadata = match std::fs::metadata(entry.path()) {
+                Ok(a) => a,
+                Err(_) => continue,
+            };
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|a| a.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |a| a.as_secs());
+            entries.push((!metadata.is_dir(), name, metadata.len(), mtime));
+        }
+    }
+    entries.sort();
+
+    let title = escape_html(req.path());
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n<body>\n<h1>Index of {}</h1>\n<table>\n",
+        title, title
     );
-    Some(_stream.unwrap())
+    if !is_root {
+        page += "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n";
+    }
+    for (is_file, name, size, mtime) in entries {
+        let slash = if is_file { "" } else { "/" };
+        page += &format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            crate::drop::url::percent_encode(&name),
+            slash,
+            escape_html(&name),
+            slash,
+            if is_file {
+                size.to_string()
+            } else {
+                "-".to_owned()
+            },
+            Time::from_timestamp(mtime)
+                .to_http_date()
+                .unwrap_or_default(),
+        );
+    }
+    page += "</table>\n</body>\n</html>\n";
+
+    res.set_version("HTTP/1.1");
+    res.set_state("200 OK");
+    res.set_header("Content-Type", "text/html; charset=utf-8".to_owned());
+    res.set_header("Content-Length", page.len().to_string());
+    res.set_content(page.into_bytes());
+    true
 }
 
-fn router_iftype_err<'a>(res: &'a mut HttpResponse, config: &'a RouterConfig) -> bool {
-    if ENABLE_CODE_NOT_FOUND.load(Ordering::Relaxed) {
-        if let Some(res404) = &config.response_404 {
-            *res = res404.clone();
+/// 检查一个将要被发送的文件或目录是否位于 roots 之中，参见 RouterConfig
+/// 路径首先按字面被规范化，它必须以某一个 root 开头，这样 `..` 无法越过 roots
+/// 然后它会被解析为真实的路径，这样指向 roots 之外的符号链接也无法越过 roots
+/// 如果不允许跟随符号链接，那么 root 之后的任何一级路径都不能是符号链接
+///
+/// 不存在的路径总是被允许的，之后它会得到 404 或 500
+fn is_contained(config: &RouterConfig, path: &str) -> bool {
+    let path = normalize_path(std::path::Path::new(path));
+    let root = match config.roots.iter().find(|(a, _)| path.starts_with(a)) {
+        Some((a, _)) => a,
+        None => return false,
+    };
+    if !FOLLOW_SYMLINKS.load(Ordering::Relaxed) {
+        let mut prefix = root.clone();
+        for a in path.strip_prefix(root).into_iter().flatten() {
+            prefix.push(a);
+            if std::fs::symlink_metadata(&prefix).is_ok_and(|a| a.file_type().is_symlink()) {
+                return false;
+            }
+        }
+    }
+    match std::fs::canonicalize(&path) {
+        Ok(real_path) => config.roots.iter().any(|(_, a)| real_path.starts_with(a)),
+        Err(_) => true,
+    }
+}
+
+/// 按字面规范化一个相对路径，即去掉其中的 `.` 和 `..` ，而不访问文件系统
+/// 例如 `export/../image-hosting/./a.png` 会被规范化为 `image-hosting/a.png`
+pub fn normalize_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut res = std::path::PathBuf::new();
+    for a in path.components() {
+        match a {
+            std::path::Component::CurDir => (),
+            std::path::Component::ParentDir if res.file_name().is_some() => {
+                res.pop();
+            }
+            a => res.push(a),
+        }
+    }
+    res
+}
+
+/// 请求的文件位于 roots 之外，或者经过了不被允许的符号链接
+fn router_iftype_forbidden<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+    path: &str,
+) -> bool {
+    log!(Warn, format!("{}{}", LOG[44], path));
+    reset_response(res);
+    res.set_version("HTTP/1.1");
+    res.set_state("403 FORBIDDEN");
+    set_error_page(res, config, req.path(), &request_id(req));
+    true
+}
+
+/// 被缓存的文件，只要文件的修改时间和大小没有改变，它就仍然有效
+/// modified, len: 缓存时文件的修改时间和大小
+/// mtime: 以秒为单位的修改时间，参见 get_validators
+/// last_modified: 预先计算的 `Last-Modified` 响应头
+/// content: 文件的全部内容，只有被完整的读入内存的文件才有，参见 get_response_content
+#[derive(Clone)]
+struct CachedFile {
+    modified: std::time::SystemTime,
+    len: u64,
+    mtime: Option<u64>,
+    last_modified: Option<String>,
+    content: Option<Arc<Vec<u8>>>,
+}
+
+/// 文件缓存，键是文件的路径，它的总大小由 `file-cache-size` 限制
+static FILE_CACHE: Mutex<LruCache<String, Arc<CachedFile>>> = Mutex::new(LruCache::new());
+
+/// 每一项除了内容之外，在缓存中大约占用的字节数
+const FILE_CACHE_ENTRY_SIZE: usize = 256;
+
+/// 返回一个文件的元数据，如果它在缓存中并且没有被修改，则使用被缓存的项
+/// 文件可能在服务器启动之后被删除或者变得不可读，这时返回 Err ，并且它会被从缓存中删除
+fn get_file(path: &str) -> std::io::Result<Arc<CachedFile>> {
+    let capacity = FILE_CACHE_SIZE.load(Ordering::Relaxed) as usize;
+    let metadata = match std::fs::metadata(path) {
+        Ok(a) => a,
+        Err(e) => {
+            if capacity != 0 {
+                if let Ok(mut cache) = FILE_CACHE.lock() {
+                    cache.remove(path);
+                }
+            }
+            return Err(e);
+        }
+    };
+    let modified = metadata.modified()?;
+    if capacity != 0 {
+        if let Ok(mut cache) = FILE_CACHE.lock() {
+            if let Some(a) = cache.get(path) {
+                if a.modified == modified && a.len == metadata.len() {
+                    return Ok(a.clone());
+                }
+            }
+        }
+    }
+    let mtime = modified
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|a| a.as_secs());
+    let file = Arc::new(CachedFile {
+        modified,
+        len: metadata.len(),
+        mtime,
+        last_modified: mtime.and_then(|a| Time::from_timestamp(a).to_http_date().ok()),
+        content: None,
+    });
+    if capacity != 0 {
+        if let Ok(mut cache) = FILE_CACHE.lock() {
+            cache.insert(
+                path.to_owned(),
+                file.clone(),
+                path.len() + FILE_CACHE_ENTRY_SIZE,
+                capacity,
+            );
+        }
+    }
+    Ok(file)
+}
+
+/// 读取一个文件的全部内容，如果启用了文件缓存，内容会被缓存，直到文件被修改
+fn get_response_content(path: &str) -> std::io::Result<Vec<u8>> {
+    let file = get_file(path)?;
+    if let Some(a) = &file.content {
+        return Ok(a.to_vec());
+    }
+    let content = std::fs::read(path)?;
+    let capacity = FILE_CACHE_SIZE.load(Ordering::Relaxed) as usize;
+    if capacity != 0 {
+        // 文件可能在读取元数据之后被修改，这时被缓存的修改时间是旧的，所以下一次请求会重新读取它
+        let mut file = (*file).clone();
+        file.content = Some(Arc::new(content.clone()));
+        if let Ok(mut cache) = FILE_CACHE.lock() {
+            cache.insert(
+                path.to_owned(),
+                Arc::new(file),
+                path.len() + FILE_CACHE_ENTRY_SIZE + content.len(),
+                capacity,
+            );
+        }
+    }
+    Ok(content)
+}
+
+/// 对于足够大的文件，打开它以便流式的发送，而不是一次性读入内存
+/// 需要被 inject 替换或者需要经过 Pipe 的文件必须完整的读入内存，所以不会被流式的发送
+fn get_response_content_stream(
+    info: &ServeFileData,
+    path: &str,
+) -> Option<(
+    std::sync::Arc<std::sync::Mutex<dyn std::io::Read + Send>>,
+    u64,
+)> {
+    if info.replace.is_some() || ENABLE_PIPE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let file = std::fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    if len <= STREAM_THRESHOLD.load(Ordering::Relaxed).into() {
+        return None;
+    }
+    Some((std::sync::Arc::new(std::sync::Mutex::new(file)), len))
+}
+
+/// 预压缩文件的内容编码及其扩展名，在客户端同样接受时，靠前的优先
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// 查找与 path 相邻的、客户端可以接受的预压缩文件，例如 `index.html.gz`
+/// 返回 (内容编码, 扩展名)
+fn get_precompressed<T>(
+    req: &HttpRequest<T>,
+    config: &RouterConfig,
+    path: &str,
+) -> Option<(&'static str, &'static str)> {
+    let accept = req.get_header("Accept-Encoding")?;
+    let supported: Vec<&'static str> = PRECOMPRESSED
+        .iter()
+        .filter(|(_, ext)| {
+            let path = path.to_owned() + ext;
+            std::path::Path::new(&path).is_file() && is_contained(config, &path)
+        })
+        .map(|(encoding, _)| *encoding)
+        .collect();
+    let encoding = negotiate_encoding(accept, &supported)?;
+    PRECOMPRESSED.into_iter().find(|(a, _)| *a == encoding)
+}
+
+/// 根据文件的大小和修改时间生成验证器，返回 (ETag, 以秒为单位的修改时间)
+/// 这样不需要读取整个文件来计算摘要，文件被修改后，两者中至少有一个会改变
+/// encoding: 预压缩文件的内容编码，它会被附加到 ETag 中，以免与原文件的 ETag 相同
+fn get_validators(file: &CachedFile, encoding: Option<&str>) -> Option<(String, u64)> {
+    let mtime = file.mtime?;
+    let etag = match encoding {
+        Some(a) => format!("\"{:x}-{:x}-{}\"", file.len, mtime, a),
+        None => format!("\"{:x}-{:x}\"", file.len, mtime),
+    };
+    Some((etag, mtime))
+}
+
+/// 判断客户端缓存的内容是否仍然有效，参见 RFC 9110 13.1.2 和 13.1.3
+/// 如果存在 `If-None-Match` ，则忽略 `If-Modified-Since`
+fn is_not_modified<T>(req: &HttpRequest<T>, etag: &str, mtime: u64) -> bool {
+    if let Some(tags) = req.get_header("If-None-Match") {
+        // 这里使用弱比较，即忽略 `W/` 前缀
+        return tags == "*"
+            || tags
+                .split(',')
+                .any(|a| a.trim().trim_start_matches("W/") == etag);
+    }
+    match req
+        .get_header("If-Modified-Since")
+        .and_then(|a| parse_http_date(a))
+    {
+        Some(since) => mtime <= since,
+        None => false,
+    }
+}
+
+/// 返回 `304 Not Modified` ，它没有主体，但保留 ETag 和 Last-Modified
+fn router_iftype_not_modified(res: &mut HttpResponse, path: &str) -> bool {
+    res.set_version("HTTP/1.1");
+    res.set_state("304 NOT MODIFIED");
+    res.remove_header("Content-Type");
+    log!(Debug, format!("{}{}", LOG[14], path));
+    true
+}
+
+/// 一个请求中最多允许的范围数，超过它的 `Range` 请求头会被忽略，以避免过多的小范围拖慢服务器
+const RANGE_MAX_COUNT: usize = 16;
+
+/// 处理 `Range` 请求头，返回 None 意味着应该忽略该请求头并返回整个文件
+/// 一个范围会返回 `206 Partial Content` ，多个范围会以 `multipart/byteranges` 的形式返回
+/// 参见[此文档](https://www.rfc-editor.org/rfc/rfc9110#section-14)
+fn router_iftype_range<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+    info: &ServeFileData,
+    path: &str,
+    range: &str,
+    validators: Option<&(String, u64)>,
+) -> Option<bool> {
+    // If-Range 只有与当前的验证器完全相同时才会匹配，否则应该返回整个文件，参见 RFC 9110 13.1.5
+    if let Some(if_range) = req.get_header("If-Range") {
+        let (etag, mtime) = validators?;
+        let matched = if if_range.starts_with('"') {
+            if_range == etag
+        } else {
+            parse_http_date(if_range) == Some(*mtime)
+        };
+        if !matched {
+            return None;
+        }
+    }
+    let len = std::fs::metadata(path).ok()?.len();
+    let ranges = match parse_range(range, len) {
+        Ok(a) if a.len() <= RANGE_MAX_COUNT => a,
+        Err(RangeError::Unsatisfiable) => {
+            // 错误页面不是被压缩的文件，也没有文件的验证器
+            reset_response(res);
             res.set_version("HTTP/1.1");
-            res.set_state("404 NOT FOUND");
-            res.set_header(
-                "Content-Length",
-                res.content_ref().clone().unwrap().len().to_string(),
+            res.set_state("416 RANGE NOT SATISFIABLE");
+            res.set_header("Content-Range", format!("bytes */{}", len));
+            set_error_page(res, config, req.path(), &request_id(req));
+            return Some(true);
+        }
+        _ => return None,
+    };
+    let open_range = |(first, last): (u64, u64)| -> Option<std::io::Take<std::fs::File>> {
+        let mut file = std::fs::File::open(path).ok()?;
+        file.seek(std::io::SeekFrom::Start(first)).ok()?;
+        Some(file.take(last - first + 1))
+    };
+
+    // 先打开文件，打开失败时返回 None ，不能留下 206 的状态和头部
+    if let [(first, last)] = ranges[..] {
+        let file = open_range((first, last))?;
+        res.set_version("HTTP/1.1");
+        res.set_state("206 PARTIAL CONTENT");
+        res.set_header("Content-Range", format!("bytes {}-{}/{}", first, last, len));
+        res.set_content_stream(
+            std::sync::Arc::new(std::sync::Mutex::new(file)),
+            Some(last - first + 1),
+        );
+    } else {
+        let boundary = format!(
+            "{:016x}",
+            crate::drop::random::random_init(
+                (crate::drop::time::Time::nsec().unwrap_or(0) as u32) << 16 | len as u32
+            )
+            .next_u64()
+        );
+        let mut total = 0;
+        let mut stream: Box<dyn Read + Send> = Box::new(std::io::empty());
+        for (first, last) in ranges {
+            let head = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, info.content_type, first, last, len
+            );
+            total += head.len() as u64 + last - first + 1;
+            stream = Box::new(
+                stream
+                    .chain(std::io::Cursor::new(head))
+                    .chain(open_range((first, last))?),
             );
-            return true;
         }
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        total += tail.len() as u64;
+        stream = Box::new(stream.chain(std::io::Cursor::new(tail)));
+        res.set_version("HTTP/1.1");
+        res.set_state("206 PARTIAL CONTENT");
+        res.set_header(
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        );
+        res.set_content_stream(
+            std::sync::Arc::new(std::sync::Mutex::new(stream)),
+            Some(total),
+        );
+    }
+    log!(Debug, format!("{}{} ({})", LOG[14], path, range));
+    Some(true)
+}
 
+/// 对 OPTIONS 请求，只返回该路由允许的方法
+fn router_iftype_options(res: &mut HttpResponse, info: &ServeFileData) -> bool {
+    res.set_version("HTTP/1.1");
+    res.set_state("204 NO CONTENT");
+    res.set_header("Allow", info.allow_header());
+    true
+}
+
+/// 对 `OPTIONS *` 请求，返回整个服务器允许的方法，即所有路由允许的方法的并集
+fn router_iftype_options_server(res: &mut HttpResponse, config: &RouterConfig) -> bool {
+    let mut methods = if config.serve_dirs.is_empty() {
+        vec![]
+    } else {
+        ServeFileData::default_methods()
+    };
+    for a in config.serve_files_info.values().flat_map(|a| &a.methods) {
+        if !methods.contains(a) {
+            methods.push(a.clone());
+        }
+    }
+    if !methods.iter().any(|a| a == "OPTIONS") {
+        methods.push("OPTIONS".to_owned());
+    }
+    methods.sort();
+    res.set_version("HTTP/1.1");
+    res.set_state("200 OK");
+    res.set_header("Allow", methods.join(", "));
+    true
+}
+
+fn router_iftype_method_not_allowed<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+    info: &ServeFileData,
+) -> bool {
+    res.set_version("HTTP/1.1");
+    res.set_state("405 METHOD NOT ALLOWED");
+    res.set_header("Allow", info.allow_header());
+    set_error_page(res, config, req.path(), &request_id(req));
+    true
+}
+
+fn router_iftype_err<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+) -> bool {
+    if ENABLE_CODE_NOT_FOUND.load(Ordering::Relaxed) {
         res.set_version("HTTP/1.1");
         res.set_state("404 NOT FOUND");
+        set_error_page(res, config, req.path(), &request_id(req));
         true
     } else {
         false
     }
 }
 
-fn router_iftype_replace<'a>(
-    req: HttpRequest<std::net::TcpStream>,
-    res: &'a mut HttpResponse,
-    config: &'a RouterConfig,
+/// 服务器内部的错误，例如要发送的文件已经不存在了，返回 `500 INTERNAL SERVER ERROR` 而不是关闭连接
+fn router_iftype_internal_error<T>(
+    req: &HttpRequest<T>,
+    res: &mut HttpResponse,
+    config: &RouterConfig,
+) -> bool {
+    let request_id = request_id(req);
+    log!(Error, format!("{}{} ({})", LOG[42], req.path(), request_id));
+    reset_response(res);
+    res.set_version("HTTP/1.1");
+    res.set_state("500 INTERNAL SERVER ERROR");
+    set_error_page(res, config, req.path(), &request_id);
+    true
+}
+
+/// 丢弃已经为发送文件而设置的响应头，例如 `ETag` 和 `Content-Encoding` ，以便改为发送一个错误
+fn reset_response(res: &mut HttpResponse) {
+    *res = HttpResponse::new();
+    res.set_default_headers("Tiny-Tiny-Web/2")
+        .result_timeerr_default();
+}
+
+fn router_iftype_replace(
+    res: &mut HttpResponse,
+    info: &ServeFileData,
     replaces: &Vec<ReplaceData>,
     str: String,
 ) -> bool {
     res.set_version("HTTP/1.1");
     res.set_state("200 OK");
-    res.set_header(
-        "Content-Type",
-        config
-            .serve_files_info
-            .get(&req.url().to_owned())
-            .unwrap()
-            .content_type
-            .clone(),
-    );
+    res.set_header("Content-Type", info.content_type.clone());
     let mut final_str = String::new();
     for e in replaces {
         final_str = str.replace("$_gcflag", &e.content);
@@ -134,3 +747,77 @@ fn router_iftype_replace<'a>(
     res.set_content(final_str.into());
     true
 }
+
+/// 将 res 的主体设置为与其状态码对应的错误页面，没有设置错误页面时，主体为空
+/// 错误页面中的以下标记会被替换：
+/// `$_status`: 完整的状态，例如 `404 NOT FOUND`
+/// `$_code`: 状态码，例如 `404`
+/// `$_path`: 请求的路径，它会被转义，以免被当作 HTML 解释
+/// `$_request_id`: 这个请求的 ID ，它同时也是 `X-Request-Id` 响应头，以便用户报告错误时可以找到对应的日志
+pub fn set_error_page(res: &mut HttpResponse, config: &RouterConfig, path: &str, request_id: &str) {
+    res.set_header("X-Request-Id", request_id.to_owned());
+    let state = res.state().clone();
+    let code = state.get(..3).unwrap_or_default();
+    match code.parse().ok().and_then(|a| config.error_pages.get(&a)) {
+        Some(page) => {
+            let page = page
+                .replace("$_status", &state)
+                .replace("$_code", code)
+                .replace("$_request_id", request_id)
+                .replace("$_path", &escape_html(path));
+            res.set_header("Content-Type", "text/html; charset=utf-8".to_owned());
+            res.set_header("Content-Length", page.len().to_string());
+            res.set_content(page.into_bytes());
+        }
+        None => {
+            res.remove_header("Content-Type");
+            res.set_header("Content-Length", "0".to_owned());
+            res.set_content(vec![]);
+        }
+    }
+}
+
+fn escape_html(str: &str) -> String {
+    let mut res = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => res += "&amp;",
+            '<' => res += "&lt;",
+            '>' => res += "&gt;",
+            '"' => res += "&quot;",
+            '\'' => res += "&#39;",
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// 如果请求带有合法的 `X-Request-Id` ，例如它是由前面的代理生成的，则沿用它，否则生成一个新的
+pub fn request_id<T>(req: &HttpRequest<T>) -> String {
+    match req.get_header("X-Request-Id") {
+        Some(a)
+            if (1..=64).contains(&a.len())
+                && a.bytes()
+                    .all(|a| a.is_ascii_alphanumeric() || b"-_.".contains(&a)) =>
+        {
+            a.clone()
+        }
+        _ => new_request_id(),
+    }
+}
+
+/// 生成一个 16 位十六进制数的请求 ID
+pub fn new_request_id() -> String {
+    // 同一时刻可能有多个线程在生成请求 ID ，所以加入一个递增的序号
+    static SEQ: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+    format!(
+        "{:016x}",
+        crate::drop::random::random_init(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |a| a.subsec_nanos())
+                ^ SEQ.fetch_add(1, Ordering::Relaxed).rotate_left(16)
+        )
+        .next_u64()
+    )
+}